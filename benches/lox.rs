@@ -0,0 +1,238 @@
+//! Runs each program under `benches/lox` and reports its wall time and how
+//! much it allocates, optionally against a saved baseline:
+//!
+//!     cargo bench --bench lox -- --save-baseline before
+//!     cargo bench --bench lox -- --baseline before [filter]
+//!
+//! Lox doesn't have functions or classes yet, so there is no recursive
+//! Fibonacci, method call or binary-trees benchmark: `fib_iterative` computes
+//! Fibonacci numbers in a loop, and the other programs measure loops, native
+//! calls, scopes and strings in their own right.
+
+extern crate rlox;
+#[macro_use]
+extern crate serde_json;
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use rlox::Lox;
+use serde_json::Value;
+
+/// Timed runs per program, after one untimed warm-up run.
+const RUNS: usize = 5;
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+static ALLOCATED_BYTES: AtomicU64 = AtomicU64::new(0);
+
+/// Counts every allocation and reallocation made through the system allocator.
+struct CountingAllocator;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED_BYTES.fetch_add(layout.size() as u64, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED_BYTES.fetch_add(new_size as u64, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+struct Measurement {
+    name: String,
+    /// The median of the timed runs.
+    time: Duration,
+    allocations: u64,
+    allocated_bytes: u64,
+}
+
+impl Measurement {
+    fn to_json(&self) -> Value {
+        json!({
+            "millis": millis(self.time),
+            "allocations": self.allocations,
+            "allocated_bytes": self.allocated_bytes,
+        })
+    }
+}
+
+#[derive(Default)]
+struct Options {
+    save_baseline: Option<String>,
+    baseline: Option<String>,
+    filter: Option<String>,
+}
+
+impl Options {
+    fn parse(args: &[String]) -> Option<Self> {
+        let mut options = Options::default();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                // Passed by `cargo bench` to every bench target.
+                "--bench" => {}
+                "--save-baseline" => options.save_baseline = Some(args.next()?.to_owned()),
+                "--baseline" => options.baseline = Some(args.next()?.to_owned()),
+                _ if arg.starts_with('-') => return None,
+                _ if options.filter.is_none() => options.filter = Some(arg.to_owned()),
+                _ => return None,
+            }
+        }
+        Some(options)
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let options = match Options::parse(&args[1..]) {
+        Some(options) => options,
+        None => {
+            eprintln!("Usage: cargo bench --bench lox -- [--save-baseline <name>] [--baseline <name>] [filter]");
+            process::exit(64);
+        }
+    };
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+
+    let baseline = match options.baseline {
+        Some(ref name) => match load_baseline(&baseline_path(root, name)) {
+            Ok(baseline) => Some(baseline),
+            Err(err) => {
+                eprintln!("Could not read baseline '{}': {}", name, err);
+                process::exit(66);
+            }
+        },
+        None => None,
+    };
+
+    let mut programs: Vec<PathBuf> = fs::read_dir(root.join("benches").join("lox"))
+        .expect("benchmark directory should be readable")
+        .map(|entry| entry.expect("benchmark directory should be readable").path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "lox"))
+        .collect();
+    programs.sort();
+
+    println!("{:<16} {:>10} {:>12} {:>14}   vs baseline", "benchmark", "time ms", "allocations", "bytes");
+    let mut measurements = Vec::new();
+    for path in programs {
+        let name = path.file_stem().unwrap().to_string_lossy().into_owned();
+        if options.filter.as_ref().is_some_and(|filter| !name.contains(filter.as_str())) {
+            continue;
+        }
+        let measurement = measure(name, &path);
+        let comparison = baseline
+            .as_ref()
+            .and_then(|baseline| baseline.get(&measurement.name))
+            .map_or_else(String::new, |saved| compare(&measurement, saved));
+        println!(
+            "{:<16} {:>10.2} {:>12} {:>14}   {}",
+            measurement.name,
+            millis(measurement.time),
+            measurement.allocations,
+            measurement.allocated_bytes,
+            comparison
+        );
+        measurements.push(measurement);
+    }
+
+    if let Some(ref name) = options.save_baseline {
+        let path = baseline_path(root, name);
+        if let Err(err) = save_baseline(&path, &measurements) {
+            eprintln!("Could not save baseline '{}': {}", name, err);
+            process::exit(74);
+        }
+        println!("\nSaved baseline '{}' to {}", name, path.display());
+    }
+}
+
+fn measure(name: String, path: &Path) -> Measurement {
+    let source = fs::read_to_string(path).expect("benchmark should be readable");
+    run(&name, &source);
+
+    let mut times = Vec::with_capacity(RUNS);
+    let mut allocations = 0;
+    let mut allocated_bytes = 0;
+    for _ in 0..RUNS {
+        let allocations_before = ALLOCATIONS.load(Ordering::Relaxed);
+        let bytes_before = ALLOCATED_BYTES.load(Ordering::Relaxed);
+        times.push(run(&name, &source));
+        // Every run allocates the same, so the last one stands for them all.
+        allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations_before;
+        allocated_bytes = ALLOCATED_BYTES.load(Ordering::Relaxed) - bytes_before;
+    }
+    times.sort();
+
+    Measurement {
+        name,
+        time: times[RUNS / 2],
+        allocations,
+        allocated_bytes,
+    }
+}
+
+/// Runs a program in a fresh interpreter, discarding what it prints.
+fn run(name: &str, source: &str) -> Duration {
+    let mut lox = Lox::new();
+    lox.set_output(io::sink());
+    let start = Instant::now();
+    if let Err(err) = lox.run(source) {
+        eprintln!("{} failed:\n{}", name, err);
+        process::exit(70);
+    }
+    start.elapsed()
+}
+
+fn compare(measurement: &Measurement, saved: &Value) -> String {
+    let change = |now: f64, before: Option<f64>| match before {
+        Some(before) if before > 0.0 => format!("{:+.1}%", (now - before) / before * 100.0),
+        _ => "n/a".to_owned(),
+    };
+    format!(
+        "{} time, {} allocations",
+        change(millis(measurement.time), saved["millis"].as_f64()),
+        change(measurement.allocations as f64, saved["allocations"].as_f64())
+    )
+}
+
+fn baseline_path(root: &Path, name: &str) -> PathBuf {
+    root.join("target").join("lox-bench").join(format!("{}.json", name))
+}
+
+fn load_baseline(path: &Path) -> io::Result<serde_json::Map<String, Value>> {
+    let contents = fs::read_to_string(path)?;
+    match serde_json::from_str(&contents) {
+        Ok(Value::Object(baseline)) => Ok(baseline),
+        _ => Err(io::Error::new(io::ErrorKind::InvalidData, "baseline is not a JSON object")),
+    }
+}
+
+fn save_baseline(path: &Path, measurements: &[Measurement]) -> io::Result<()> {
+    let mut baseline = serde_json::Map::new();
+    for measurement in measurements {
+        baseline.insert(measurement.name.to_owned(), measurement.to_json());
+    }
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, serde_json::to_string_pretty(&Value::Object(baseline))?)
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
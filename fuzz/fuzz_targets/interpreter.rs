@@ -0,0 +1,214 @@
+//! Runs arbitrary well-formed programs under limits, so that the interpreter
+//! is exercised past the parser's error paths. Runtime errors are expected;
+//! panics, runs that take well past the time limit, and running out of memory
+//! with more in use than the memory limit allows, are not.
+
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+#[macro_use]
+extern crate arbitrary;
+extern crate rlox;
+
+use std::fmt;
+use std::io;
+use std::time::{Duration, Instant};
+
+use rlox::{Limit, Limits, Lox, LoxError};
+
+#[derive(Arbitrary, Debug)]
+struct Program(Vec<Stmt>);
+
+#[derive(Arbitrary, Debug)]
+enum Stmt {
+    Print(Expr),
+    Expression(Expr),
+    Var(Name, Expr),
+    Block(Vec<Stmt>),
+    If(Expr, Vec<Stmt>, Option<Vec<Stmt>>),
+    While(Expr, Vec<Stmt>),
+}
+
+#[derive(Arbitrary, Debug)]
+enum Expr {
+    Integer(i64),
+    Number(f64),
+    String(String),
+    Boolean(bool),
+    Nil,
+    Variable(Name),
+    Assign(Name, Box<Expr>),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(Box<Expr>, BinaryOp, Box<Expr>),
+    Logical(Box<Expr>, bool, Box<Expr>),
+    Grouping(Box<Expr>),
+    Call(Box<Expr>, Vec<Expr>),
+}
+
+#[derive(Arbitrary, Debug)]
+enum Name {
+    A,
+    B,
+    C,
+    Clock,
+}
+
+#[derive(Arbitrary, Debug)]
+enum UnaryOp {
+    Negate,
+    Not,
+}
+
+#[derive(Arbitrary, Debug)]
+enum BinaryOp {
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    TildeSlash,
+    Percent,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Equal,
+    NotEqual,
+}
+
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for stmt in &self.0 {
+            writeln!(f, "{}", stmt)?;
+        }
+        Ok(())
+    }
+}
+
+fn block(f: &mut fmt::Formatter, statements: &[Stmt]) -> fmt::Result {
+    write!(f, "{{ ")?;
+    for stmt in statements {
+        write!(f, "{} ", stmt)?;
+    }
+    write!(f, "}}")
+}
+
+impl fmt::Display for Stmt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Stmt::Print(ref expr) => write!(f, "print {};", expr),
+            Stmt::Expression(ref expr) => write!(f, "{};", expr),
+            Stmt::Var(ref name, ref initializer) => write!(f, "var {} = {};", name, initializer),
+            Stmt::Block(ref statements) => block(f, statements),
+            Stmt::If(ref condition, ref then_clause, ref else_clause) => {
+                write!(f, "if ({}) ", condition)?;
+                block(f, then_clause)?;
+                if let Some(ref else_clause) = *else_clause {
+                    write!(f, " else ")?;
+                    block(f, else_clause)?;
+                }
+                Ok(())
+            }
+            Stmt::While(ref condition, ref body) => {
+                write!(f, "while ({}) ", condition)?;
+                block(f, body)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            // Lox has no syntax for negative, infinite or NaN literals, and
+            // a float needs a fractional part to not scan as an integer.
+            Expr::Integer(n) => write!(f, "{}", n.unsigned_abs().min(i64::MAX as u64)),
+            Expr::Number(n) if n.is_finite() && n.fract() == 0.0 => write!(f, "{}.0", n.abs()),
+            Expr::Number(n) if n.is_finite() => write!(f, "{}", n.abs()),
+            Expr::Number(_) => write!(f, "0.0"),
+            Expr::String(ref s) => write!(f, "\"{}\"", s.replace('"', "")),
+            Expr::Boolean(b) => write!(f, "{}", b),
+            Expr::Nil => write!(f, "nil"),
+            Expr::Variable(ref name) => write!(f, "{}", name),
+            Expr::Assign(ref name, ref value) => write!(f, "({} = {})", name, value),
+            Expr::Unary(UnaryOp::Negate, ref e) => write!(f, "-{}", e),
+            Expr::Unary(UnaryOp::Not, ref e) => write!(f, "!{}", e),
+            Expr::Binary(ref lhs, ref op, ref rhs) => write!(f, "({} {} {})", lhs, op, rhs),
+            Expr::Logical(ref lhs, and, ref rhs) => write!(f, "({} {} {})", lhs, if and { "and" } else { "or" }, rhs),
+            Expr::Grouping(ref e) => write!(f, "({})", e),
+            Expr::Call(ref callee, ref arguments) => {
+                write!(f, "{}(", callee)?;
+                for (i, argument) in arguments.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", argument)?;
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+impl fmt::Display for Name {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            Name::A => "a",
+            Name::B => "b",
+            Name::C => "c",
+            Name::Clock => "clock",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl fmt::Display for BinaryOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let op = match *self {
+            BinaryOp::Plus => "+",
+            BinaryOp::Minus => "-",
+            BinaryOp::Star => "*",
+            BinaryOp::Slash => "/",
+            BinaryOp::TildeSlash => "~/",
+            BinaryOp::Percent => "%",
+            BinaryOp::Less => "<",
+            BinaryOp::LessEqual => "<=",
+            BinaryOp::Greater => ">",
+            BinaryOp::GreaterEqual => ">=",
+            BinaryOp::Equal => "==",
+            BinaryOp::NotEqual => "!=",
+        };
+        write!(f, "{}", op)
+    }
+}
+
+const MAX_WALL_TIME: Duration = Duration::from_secs(1);
+const MAX_MEMORY: usize = 1 << 20;
+
+/// How long past the time limit a run may go, since the clock is only read
+/// every so many steps and a single step can take a while.
+const WALL_TIME_MARGIN: Duration = Duration::from_secs(1);
+
+fuzz_target!(|program: Program| {
+    let source = program.to_string();
+    for &optimize in &[false, true] {
+        let mut lox = Lox::new();
+        lox.set_output(io::sink());
+        lox.set_error_output(io::sink());
+        lox.set_optimize(optimize);
+        lox.set_limits(Limits {
+            max_steps: Some(10_000),
+            max_wall_time: Some(MAX_WALL_TIME),
+            max_call_depth: Some(64),
+            max_memory: Some(MAX_MEMORY),
+        });
+        let started = Instant::now();
+        let result = lox.run(&source);
+        let elapsed = started.elapsed();
+        assert!(elapsed <= MAX_WALL_TIME + WALL_TIME_MARGIN, "ran for {:?}", elapsed);
+        if let Err(LoxError::Runtime(ref err)) = result {
+            if err.limit == Some(Limit::Memory(MAX_MEMORY)) {
+                assert!(lox.memory_used() <= MAX_MEMORY, "{} bytes in use", lox.memory_used());
+            }
+        }
+    }
+});
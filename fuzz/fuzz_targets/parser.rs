@@ -0,0 +1,95 @@
+//! Feeds arbitrary token streams to the parser, including ones the scanner
+//! would never produce, such as a number token without a number in it.
+
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate arbitrary;
+extern crate rlox;
+
+use arbitrary::{Arbitrary, Unstructured};
+use rlox::{Literal, Lox, Token, TokenType};
+
+/// Every kind of token, with the lexeme it's usually scanned from.
+const TOKENS: &[(TokenType, &str)] = &[
+    (TokenType::LEFT_PAREN, "("),
+    (TokenType::RIGHT_PAREN, ")"),
+    (TokenType::LEFT_BRACE, "{"),
+    (TokenType::RIGHT_BRACE, "}"),
+    (TokenType::COMMA, ","),
+    (TokenType::DOT, "."),
+    (TokenType::MINUS, "-"),
+    (TokenType::PLUS, "+"),
+    (TokenType::SEMICOLON, ";"),
+    (TokenType::SLASH, "/"),
+    (TokenType::STAR, "*"),
+    (TokenType::PERCENT, "%"),
+    (TokenType::BANG, "!"),
+    (TokenType::BANG_EQUAL, "!="),
+    (TokenType::EQUAL, "="),
+    (TokenType::EQUAL_EQUAL, "=="),
+    (TokenType::GREATER, ">"),
+    (TokenType::GREATER_EQUAL, ">="),
+    (TokenType::LESS, "<"),
+    (TokenType::LESS_EQUAL, "<="),
+    (TokenType::TILDE_SLASH, "~/"),
+    (TokenType::IDENTIFIER, "a"),
+    (TokenType::IDENTIFIER, "b"),
+    (TokenType::STRING, "\"s\""),
+    (TokenType::NUMBER, "1"),
+    (TokenType::NUMBER, "1.5"),
+    (TokenType::AND, "and"),
+    (TokenType::CLASS, "class"),
+    (TokenType::ELSE, "else"),
+    (TokenType::FALSE, "false"),
+    (TokenType::FUN, "fun"),
+    (TokenType::FOR, "for"),
+    (TokenType::IF, "if"),
+    (TokenType::NIL, "nil"),
+    (TokenType::OR, "or"),
+    (TokenType::PRINT, "print"),
+    (TokenType::RETURN, "return"),
+    (TokenType::SUPER, "super"),
+    (TokenType::THIS, "this"),
+    (TokenType::TRUE, "true"),
+    (TokenType::VAR, "var"),
+    (TokenType::WHILE, "while"),
+    (TokenType::EOF, ""),
+    (TokenType::COMMENT, "// c"),
+    (TokenType::WHITESPACE, " "),
+    (TokenType::NEWLINE, "\n"),
+];
+
+fn token(u: &mut Unstructured, line: i32) -> arbitrary::Result<Token> {
+    let &(token_type, lexeme) = u.choose(TOKENS)?;
+    let literal = match u.int_in_range(0..=5)? {
+        0 => Literal::String(String::arbitrary(u)?.into()),
+        1 => Literal::Integer(i64::arbitrary(u)?),
+        2 => Literal::Number(f64::arbitrary(u)?),
+        3 => Literal::Boolean(bool::arbitrary(u)?),
+        // Usually give the token the literal it would be scanned with.
+        _ => match token_type {
+            TokenType::STRING => Literal::String("s".into()),
+            TokenType::NUMBER if lexeme == "1" => Literal::Integer(1),
+            TokenType::NUMBER => Literal::Number(1.5),
+            _ => Literal::Nil,
+        },
+    };
+    Ok(Token::new(token_type, lexeme, literal, line, 1))
+}
+
+fuzz_target!(|data: &[u8]| {
+    let mut u = Unstructured::new(data);
+    let mut tokens = Vec::new();
+    let mut line = 1;
+    while !u.is_empty() {
+        match token(&mut u, line) {
+            Ok(token) => tokens.push(token),
+            Err(_) => break,
+        }
+        if u.ratio(1, 8).unwrap_or(false) {
+            line += 1;
+        }
+    }
+    let _ = Lox::new().parse_tokens(tokens);
+});
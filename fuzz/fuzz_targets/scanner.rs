@@ -0,0 +1,19 @@
+//! Feeds arbitrary text to the scanner, and whatever it scans to the parser.
+
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate rlox;
+
+use std::str;
+
+use rlox::Lox;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(source) = str::from_utf8(data) {
+        let lox = Lox::new();
+        let _ = lox.dump_tokens(source, true);
+        let _ = lox.dump_tokens(source, false);
+        let _ = lox.parse(source);
+    }
+});
@@ -0,0 +1,508 @@
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::path::Path;
+use std::rc::Rc;
+
+use serde_json::Value;
+
+use debugger::{self, PauseReason, Stepper};
+use framing;
+use interpreter::{ExecutionHook, Interpreter, RuntimeError, RuntimeResult};
+use lox::Lox;
+use parser::ast::{Stmt, AST};
+use parser::location::stmt_line;
+use scanner::{Literal, Token, TokenType};
+
+/// Lox has no threads and, without functions, only ever one stack frame.
+const THREAD_ID: i64 = 1;
+const FRAME_ID: i64 = 1;
+
+/// A Debug Adapter Protocol server that launches one Lox script and lets an
+/// editor pause, step and inspect it.
+pub struct DebugAdapter<R, W> {
+    session: Rc<RefCell<Session<R, W>>>,
+}
+
+/// The connection and debugging state, shared by the adapter, the hook that
+/// pauses the interpreter, and the writer forwarding program output.
+struct Session<R, W> {
+    input: R,
+    output: W,
+    seq: i64,
+    stepper: Stepper,
+    program: Option<Program>,
+    disconnected: bool,
+}
+
+struct Program {
+    path: String,
+    source: String,
+    statement_lines: Option<BTreeSet<i32>>,
+}
+
+impl<R: BufRead + 'static, W: Write + 'static> DebugAdapter<R, W> {
+    pub fn new(input: R, output: W) -> Self {
+        let session = Session {
+            input,
+            output,
+            seq: 0,
+            stepper: Stepper::new(),
+            program: None,
+            disconnected: false,
+        };
+        DebugAdapter { session: Rc::new(RefCell::new(session)) }
+    }
+
+    /// Serves requests until the client disconnects or closes the stream.
+    pub fn run(&mut self) -> io::Result<i32> {
+        loop {
+            let message = match self.session.borrow_mut().read_message()? {
+                Some(message) => message,
+                None => break,
+            };
+            let command = message["command"].as_str().unwrap_or("").to_owned();
+            let arguments = &message["arguments"];
+            let result = {
+                let mut session = self.session.borrow_mut();
+                match command.as_str() {
+                    "initialize" => Ok(json!({
+                        "supportsConfigurationDoneRequest": true,
+                        "supportsEvaluateForHovers": true,
+                    })),
+                    "launch" => session.launch(arguments),
+                    "setBreakpoints" => session.set_breakpoints(arguments),
+                    "configurationDone" | "disconnect" => Ok(Value::Null),
+                    "threads" => Ok(threads()),
+                    "stackTrace" | "scopes" | "variables" | "evaluate" | "continue" | "next" | "stepIn" |
+                    "stepOut" => Err("The program is not paused.".to_owned()),
+                    _ => Err(format!("Unsupported request '{}'.", command)),
+                }
+            };
+            self.session.borrow_mut().respond(&message, result)?;
+
+            match command.as_str() {
+                "initialize" => self.session.borrow_mut().event("initialized", Value::Null)?,
+                "configurationDone" => self.run_program()?,
+                "disconnect" => break,
+                _ => {}
+            }
+            if self.session.borrow().disconnected {
+                break;
+            }
+        }
+        Ok(0)
+    }
+
+    fn run_program(&mut self) -> io::Result<()> {
+        let source = self.session.borrow().program.as_ref().map(|program| program.source.clone());
+        let source = match source {
+            Some(source) => source,
+            None => return self.session.borrow_mut().event("terminated", Value::Null),
+        };
+
+        let mut lox = Lox::new();
+        lox.set_output(ProgramOutput { session: Rc::clone(&self.session), buffer: Vec::new() });
+        lox.set_hook(Hook { session: Rc::clone(&self.session) });
+        let result = lox.run(&source);
+
+        let mut session = self.session.borrow_mut();
+        if session.disconnected {
+            return Ok(());
+        }
+        let exit_code = match result {
+            Ok(()) => 0,
+            Err(err) => {
+                session.event("output", json!({ "category": "stderr", "output": format!("{}\n", err) }))?;
+                err.exit_code()
+            }
+        };
+        session.event("exited", json!({ "exitCode": exit_code }))?;
+        session.event("terminated", Value::Null)
+    }
+}
+
+impl<R: BufRead, W: Write> Session<R, W> {
+    fn read_message(&mut self) -> io::Result<Option<Value>> {
+        framing::read_message(&mut self.input)
+    }
+
+    fn send(&mut self, mut message: Value) -> io::Result<()> {
+        self.seq += 1;
+        message["seq"] = json!(self.seq);
+        framing::write_message(&mut self.output, &message)
+    }
+
+    fn respond(&mut self, request: &Value, result: Result<Value, String>) -> io::Result<()> {
+        let mut response = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": result.is_ok(),
+        });
+        match result {
+            Ok(Value::Null) => {}
+            Ok(body) => response["body"] = body,
+            Err(message) => response["message"] = json!(message),
+        }
+        self.send(response)
+    }
+
+    fn event(&mut self, event: &str, body: Value) -> io::Result<()> {
+        let mut message = json!({ "type": "event", "event": event });
+        if !body.is_null() {
+            message["body"] = body;
+        }
+        self.send(message)
+    }
+
+    fn launch(&mut self, arguments: &Value) -> Result<Value, String> {
+        let path = arguments["program"].as_str().ok_or_else(|| "Missing 'program' to launch.".to_owned())?;
+        let source = fs::read_to_string(path).map_err(|err| format!("Could not read '{}': {}", path, err))?;
+        // A script that doesn't parse still launches, to report its errors.
+        let statement_lines = Lox::new().parse(&source).ok().map(|ast| statement_lines(&ast));
+
+        if arguments["noDebug"].as_bool() == Some(true) {
+            self.stepper.clear_breakpoints();
+            self.stepper.resume();
+        } else if arguments["stopOnEntry"].as_bool() != Some(true) {
+            self.stepper.resume();
+        }
+        self.program = Some(Program { path: path.to_owned(), source, statement_lines });
+        Ok(Value::Null)
+    }
+
+    /// Replaces every breakpoint, as the request always sends the full set for
+    /// a source. A breakpoint is only verified on a line where a statement starts.
+    fn set_breakpoints(&mut self, arguments: &Value) -> Result<Value, String> {
+        self.stepper.clear_breakpoints();
+        let lines: Vec<i64> = match arguments["breakpoints"].as_array() {
+            Some(breakpoints) => breakpoints.iter().filter_map(|breakpoint| breakpoint["line"].as_i64()).collect(),
+            None => Vec::new(),
+        };
+        let mut breakpoints = Vec::new();
+        for line in lines {
+            let line = line as i32;
+            let verified = match self.program {
+                Some(Program { statement_lines: Some(ref statement_lines), .. }) => statement_lines.contains(&line),
+                _ => true,
+            };
+            if verified {
+                self.stepper.add_breakpoint(line);
+            }
+            breakpoints.push(json!({ "verified": verified, "line": line }));
+        }
+        Ok(json!({ "breakpoints": breakpoints }))
+    }
+
+    /// Tells the client where the program stopped and serves its requests
+    /// until one resumes execution.
+    fn pause(&mut self, interpreter: &mut Interpreter, line: i32, reason: PauseReason) -> RuntimeResult<()> {
+        let reason = match reason {
+            PauseReason::Entry => "entry",
+            PauseReason::Step => "step",
+            PauseReason::Breakpoint => "breakpoint",
+        };
+        let stopped = json!({ "reason": reason, "threadId": THREAD_ID, "allThreadsStopped": true });
+        if self.event("stopped", stopped).is_ok() {
+            while let Ok(Some(message)) = self.read_message() {
+                let command = message["command"].as_str().unwrap_or("").to_owned();
+                let (result, resume) = self.handle_paused_request(interpreter, line, &command, &message["arguments"]);
+                if self.respond(&message, result).is_err() || command == "disconnect" {
+                    break;
+                }
+                if resume {
+                    return Ok(());
+                }
+            }
+        }
+
+        // The client is gone, so there is nobody left to debug for.
+        self.disconnected = true;
+        let token = Token::new(TokenType::EOF, "", Literal::Nil, line, 0);
+        Err(RuntimeError::new(&token, "Program stopped by the debugger."))
+    }
+
+    /// Handles a request while paused, also returning whether execution resumes.
+    fn handle_paused_request(
+        &mut self,
+        interpreter: &mut Interpreter,
+        line: i32,
+        command: &str,
+        arguments: &Value,
+    ) -> (Result<Value, String>, bool) {
+        match command {
+            "continue" => {
+                self.stepper.resume();
+                (Ok(json!({ "allThreadsContinued": true })), true)
+            }
+            "next" => {
+                self.stepper.step_over(interpreter);
+                (Ok(Value::Null), true)
+            }
+            "stepIn" => {
+                self.stepper.step_in();
+                (Ok(Value::Null), true)
+            }
+            "stepOut" => {
+                self.stepper.step_out(interpreter);
+                (Ok(Value::Null), true)
+            }
+            "threads" => (Ok(threads()), false),
+            "stackTrace" => (Ok(self.stack_trace(line)), false),
+            "scopes" => (Ok(scopes(interpreter)), false),
+            "variables" => (variables(interpreter, arguments), false),
+            "evaluate" => {
+                let expression = arguments["expression"].as_str().unwrap_or("");
+                let result = debugger::evaluate(interpreter, expression)
+                    .map(|value| json!({ "result": debugger::describe(&value), "variablesReference": 0 }));
+                (result, false)
+            }
+            "setBreakpoints" => (self.set_breakpoints(arguments), false),
+            "pause" | "disconnect" => (Ok(Value::Null), false),
+            _ => (Err(format!("Unsupported request '{}'.", command)), false),
+        }
+    }
+
+    fn stack_trace(&self, line: i32) -> Value {
+        let mut frame = json!({ "id": FRAME_ID, "name": "<script>", "line": line, "column": 1 });
+        if let Some(ref program) = self.program {
+            let name = Path::new(&program.path).file_name().map(|name| name.to_string_lossy().into_owned());
+            frame["source"] = json!({ "name": name, "path": program.path });
+        }
+        json!({ "stackFrames": [frame], "totalFrames": 1 })
+    }
+}
+
+/// Pauses the interpreter whenever the session's stepper says so.
+struct Hook<R, W> {
+    session: Rc<RefCell<Session<R, W>>>,
+}
+
+impl<R: BufRead, W: Write> ExecutionHook for Hook<R, W> {
+    fn before_statement(&mut self, interpreter: &mut Interpreter, stmt: &Stmt) -> RuntimeResult<()> {
+        let mut session = self.session.borrow_mut();
+        match session.stepper.check(interpreter, stmt) {
+            Some((line, reason)) => session.pause(interpreter, line, reason),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Sends what the program prints to the client as `output` events, a line at a time.
+struct ProgramOutput<R, W> {
+    session: Rc<RefCell<Session<R, W>>>,
+    buffer: Vec<u8>,
+}
+
+impl<R: BufRead, W: Write> ProgramOutput<R, W> {
+    fn send(&mut self, end: usize) -> io::Result<()> {
+        let text: Vec<u8> = self.buffer.drain(..end).collect();
+        let body = json!({ "category": "stdout", "output": String::from_utf8_lossy(&text) });
+        self.session.borrow_mut().event("output", body)
+    }
+}
+
+impl<R: BufRead, W: Write> Write for ProgramOutput<R, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        if let Some(index) = self.buffer.iter().rposition(|&byte| byte == b'\n') {
+            self.send(index + 1)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let end = self.buffer.len();
+        self.send(end)
+    }
+}
+
+fn threads() -> Value {
+    json!({ "threads": [{ "id": THREAD_ID, "name": "main" }] })
+}
+
+/// One scope per environment in the chain. Its variables reference is its
+/// position in the chain, counting from one as zero means "no children".
+fn scopes(interpreter: &Interpreter) -> Value {
+    let count = interpreter.scopes().len();
+    let scopes: Vec<Value> = (0..count)
+        .map(|i| {
+            let name = match i {
+                _ if i == count - 1 => "Globals".to_owned(),
+                0 => "Locals".to_owned(),
+                _ => format!("Enclosing Block {}", i),
+            };
+            json!({ "name": name, "variablesReference": i + 1, "expensive": false })
+        })
+        .collect();
+    json!({ "scopes": scopes })
+}
+
+fn variables(interpreter: &Interpreter, arguments: &Value) -> Result<Value, String> {
+    let scopes = debugger::scope_variables(interpreter);
+    let scope = arguments["variablesReference"]
+        .as_u64()
+        .and_then(|reference| (reference as usize).checked_sub(1))
+        .and_then(|index| scopes.get(index))
+        .ok_or_else(|| "Unknown variables reference.".to_owned())?;
+    let variables: Vec<Value> = scope
+        .iter()
+        .map(|(name, value)| json!({ "name": name, "value": debugger::describe(value), "variablesReference": 0 }))
+        .collect();
+    Ok(json!({ "variables": variables }))
+}
+
+/// The lines of every statement a breakpoint can stop at.
+fn statement_lines(ast: &AST) -> BTreeSet<i32> {
+    fn collect(stmt: &Stmt, lines: &mut BTreeSet<i32>) {
+        match *stmt {
+            Stmt::Block(ref statements) => {
+                for stmt in statements {
+                    collect(stmt, lines);
+                }
+                return;
+            }
+            Stmt::If(_, _, ref then_branch, ref maybe_else_branch) => {
+                collect(then_branch, lines);
+                if let Some(ref else_branch) = *maybe_else_branch {
+                    collect(else_branch, lines);
+                }
+            }
+            Stmt::While(_, _, ref body) => collect(body, lines),
+            Stmt::Expression(_) | Stmt::Print(..) | Stmt::Var(..) => {}
+        }
+        lines.extend(stmt_line(stmt));
+    }
+
+    let mut lines = BTreeSet::new();
+    for stmt in ast.root.iter() {
+        collect(stmt, &mut lines);
+    }
+    lines
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::env;
+    use std::io::Cursor;
+    use output::OutputBuffer;
+
+    fn session(name: &str, source: &str, launch: Value, requests: &[(&str, Value)]) -> Vec<Value> {
+        let path = env::temp_dir().join(format!("rlox-dap-{}-{}.lox", name, std::process::id()));
+        fs::write(&path, source).unwrap();
+        let mut launch = launch;
+        launch["program"] = json!(path.to_str().unwrap());
+
+        let mut messages = vec![("initialize", json!({ "adapterID": "rlox" })), ("launch", launch)];
+        messages.extend(requests.iter().map(|&(command, ref arguments)| (command, arguments.clone())));
+        messages.push(("disconnect", Value::Null));
+        let mut input = Vec::new();
+        for (seq, (command, arguments)) in messages.into_iter().enumerate() {
+            let request = json!({ "seq": seq + 1, "type": "request", "command": command, "arguments": arguments });
+            framing::write_message(&mut input, &request).unwrap();
+        }
+
+        let output = OutputBuffer::new();
+        DebugAdapter::new(Cursor::new(input), output.clone()).run().unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let mut reader = Cursor::new(output.contents().into_bytes());
+        let mut responses = Vec::new();
+        while let Some(message) = framing::read_message(&mut reader).unwrap() {
+            responses.push(message);
+        }
+        responses
+    }
+
+    fn response<'a>(messages: &'a [Value], command: &str) -> &'a Value {
+        messages
+            .iter()
+            .find(|message| message["type"] == "response" && message["command"] == command)
+            .unwrap_or_else(|| panic!("no response to {}", command))
+    }
+
+    fn events<'a>(messages: &'a [Value], event: &str) -> Vec<&'a Value> {
+        messages.iter().filter(|message| message["event"] == event).collect()
+    }
+
+    #[test]
+    fn test_breakpoint_scopes_and_variables() {
+        let source = "var a = 1;\n{\n  var b = a + 1;\n  print b;\n}\nprint a;\n";
+        let messages = session("breakpoints", source, json!({}), &[
+            ("setBreakpoints", json!({ "breakpoints": [{ "line": 2 }, { "line": 4 }] })),
+            ("configurationDone", Value::Null),
+            ("stackTrace", json!({ "threadId": THREAD_ID })),
+            ("scopes", json!({ "frameId": FRAME_ID })),
+            ("variables", json!({ "variablesReference": 1 })),
+            ("evaluate", json!({ "expression": "a + b", "frameId": FRAME_ID })),
+            ("continue", json!({ "threadId": THREAD_ID })),
+        ]);
+
+        assert_eq!(events(&messages, "initialized").len(), 1);
+        let breakpoints = &response(&messages, "setBreakpoints")["body"]["breakpoints"];
+        assert_eq!(*breakpoints, json!([{ "verified": false, "line": 2 }, { "verified": true, "line": 4 }]));
+
+        let stopped = events(&messages, "stopped");
+        assert_eq!(stopped.len(), 1);
+        assert_eq!(stopped[0]["body"]["reason"], "breakpoint");
+        assert_eq!(response(&messages, "stackTrace")["body"]["stackFrames"][0]["line"], 4);
+        let scopes = &response(&messages, "scopes")["body"]["scopes"];
+        assert_eq!(scopes[0]["name"], "Locals");
+        assert_eq!(scopes[1]["name"], "Globals");
+        assert_eq!(
+            response(&messages, "variables")["body"]["variables"],
+            json!([{ "name": "b", "value": "2", "variablesReference": 0 }])
+        );
+        assert_eq!(response(&messages, "evaluate")["body"]["result"], "3");
+
+        let output: Vec<&Value> = events(&messages, "output").iter().map(|event| &event["body"]["output"]).collect();
+        assert_eq!(output, vec!["2\n", "1\n"]);
+        assert_eq!(events(&messages, "exited")[0]["body"]["exitCode"], 0);
+        assert_eq!(events(&messages, "terminated").len(), 1);
+    }
+
+    #[test]
+    fn test_stepping_from_entry() {
+        let source = "var i = 0;\nwhile (i < 2)\n  i = i + 1;\nprint i;\n";
+        let messages = session("stepping", source, json!({ "stopOnEntry": true }), &[
+            ("configurationDone", Value::Null),
+            ("next", json!({ "threadId": THREAD_ID })),
+            ("stepIn", json!({ "threadId": THREAD_ID })),
+            ("stepIn", json!({ "threadId": THREAD_ID })),
+            ("stepOut", json!({ "threadId": THREAD_ID })),
+            ("evaluate", json!({ "expression": "i" })),
+            ("evaluate", json!({ "expression": "i +" })),
+            ("continue", json!({ "threadId": THREAD_ID })),
+        ]);
+
+        let reasons: Vec<&Value> = events(&messages, "stopped").iter().map(|event| &event["body"]["reason"]).collect();
+        assert_eq!(reasons, vec!["entry", "step", "step", "step", "step"]);
+        let evaluations: Vec<&Value> = messages.iter().filter(|message| message["command"] == "evaluate").collect();
+        assert_eq!(evaluations[0]["body"]["result"], "2");
+        assert_eq!(evaluations[1]["success"], false);
+        assert_eq!(events(&messages, "output")[0]["body"]["output"], "2\n");
+    }
+
+    #[test]
+    fn test_runtime_error_and_disconnect_while_paused() {
+        let messages = session("error", "print -\"a\";\n", json!({}), &[("configurationDone", Value::Null)]);
+        let output = events(&messages, "output");
+        assert_eq!(output[0]["body"]["category"], "stderr");
+        assert_eq!(events(&messages, "exited")[0]["body"]["exitCode"], 70);
+
+        let messages = session("disconnect", "print 1;\nprint 2;\n", json!({ "stopOnEntry": true }), &[
+            ("configurationDone", Value::Null),
+        ]);
+        assert_eq!(events(&messages, "stopped").len(), 1);
+        assert!(events(&messages, "output").is_empty());
+        assert!(events(&messages, "exited").is_empty());
+        assert_eq!(response(&messages, "disconnect")["success"], true);
+    }
+}
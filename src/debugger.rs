@@ -0,0 +1,368 @@
+use std::collections::BTreeSet;
+use std::io;
+use std::io::prelude::*;
+use std::mem;
+
+use interpreter::{ExecutionHook, Interpreter, RuntimeError, RuntimeResult};
+use lox_object::LoxObject;
+use parser::ast::Stmt;
+use parser::location::stmt_line;
+use parser::parser::Parser;
+use scanner::{Literal, Scanner, Token, TokenType};
+
+const HELP: &str = "\
+step, s            Run the next statement, stopping inside blocks and loops.
+next, n            Run the next statement, including anything nested in it.
+out, o             Run until the enclosing block or loop is left.
+continue, c        Run until a breakpoint is hit.
+break, b [line]    Set a breakpoint on a line, or list breakpoints.
+delete, d <line>   Remove the breakpoint on a line.
+vars, v            Show the variables in every enclosing scope.
+print, p <expr>    Evaluate an expression in the current scope.
+list, l            Show the source around the current line.
+quit, q            Stop the program.";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PauseReason {
+    Entry,
+    Step,
+    Breakpoint,
+}
+
+enum StepMode {
+    /// Run until a breakpoint is hit.
+    Continue,
+    /// Pause before the next statement.
+    Step,
+    /// Pause before the next statement that is not nested deeper than the
+    /// given depth.
+    Next(usize),
+    /// Pause before the next statement that is nested less deeply than the
+    /// given depth.
+    Out(usize),
+}
+
+/// Decides where a program being debugged pauses, from its breakpoints and the
+/// last stepping command. Both the terminal debugger and the debug adapter
+/// drive execution through one of these.
+pub struct Stepper {
+    breakpoints: BTreeSet<i32>,
+    mode: StepMode,
+    at_entry: bool,
+}
+
+impl Stepper {
+    /// Creates a stepper that pauses before the first statement.
+    pub fn new() -> Self {
+        Stepper {
+            breakpoints: BTreeSet::new(),
+            mode: StepMode::Step,
+            at_entry: true,
+        }
+    }
+
+    pub fn breakpoints(&self) -> &BTreeSet<i32> {
+        &self.breakpoints
+    }
+
+    /// Adds a breakpoint, returning false if the line already had one.
+    pub fn add_breakpoint(&mut self, line: i32) -> bool {
+        self.breakpoints.insert(line)
+    }
+
+    /// Removes a breakpoint, returning false if the line didn't have one.
+    pub fn remove_breakpoint(&mut self, line: i32) -> bool {
+        self.breakpoints.remove(&line)
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    /// Runs until the next breakpoint.
+    pub fn resume(&mut self) {
+        self.mode = StepMode::Continue;
+    }
+
+    /// Pauses before the next statement, wherever it is.
+    pub fn step_in(&mut self) {
+        self.mode = StepMode::Step;
+    }
+
+    /// Pauses before the next statement that isn't nested in the current one.
+    pub fn step_over(&mut self, interpreter: &Interpreter) {
+        self.mode = StepMode::Next(interpreter.depth());
+    }
+
+    /// Pauses before the next statement outside the innermost statement
+    /// enclosing the current one.
+    pub fn step_out(&mut self, interpreter: &Interpreter) {
+        self.mode = StepMode::Out(interpreter.depth());
+    }
+
+    /// Returns the line to pause on and why, if execution should pause before
+    /// `stmt`.
+    pub fn check(&mut self, interpreter: &Interpreter, stmt: &Stmt) -> Option<(i32, PauseReason)> {
+        let depth = interpreter.depth();
+        if let Stmt::Block(_) = *stmt {
+            // A block's first statement follows immediately, so pause there
+            // instead, and step into the block rather than over it.
+            if let StepMode::Next(next_depth) = self.mode {
+                if depth <= next_depth {
+                    self.mode = StepMode::Next(next_depth + 1);
+                }
+            }
+            return None;
+        }
+
+        let line = stmt_line(stmt)?;
+        let at_entry = mem::replace(&mut self.at_entry, false);
+        let stepped = match self.mode {
+            StepMode::Step => true,
+            StepMode::Next(next_depth) => depth <= next_depth,
+            StepMode::Out(out_depth) => depth < out_depth,
+            StepMode::Continue => false,
+        };
+        if stepped && at_entry {
+            Some((line, PauseReason::Entry))
+        } else if self.breakpoints.contains(&line) {
+            Some((line, PauseReason::Breakpoint))
+        } else if stepped {
+            Some((line, PauseReason::Step))
+        } else {
+            None
+        }
+    }
+}
+
+impl Default for Stepper {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// An interactive debugger that pauses a running program at breakpoints and
+/// after each step, reading commands from `input` while it is paused.
+pub struct Debugger<R, W> {
+    lines: Vec<String>,
+    stepper: Stepper,
+    input: R,
+    output: W,
+}
+
+impl<R: BufRead, W: Write> Debugger<R, W> {
+    /// Creates a debugger for a program that pauses before its first statement,
+    /// so that breakpoints can be set before anything runs.
+    pub fn new(source: &str, input: R, output: W) -> Self {
+        Debugger {
+            lines: source.lines().map(str::to_owned).collect(),
+            stepper: Stepper::new(),
+            input,
+            output,
+        }
+    }
+
+    fn pause(&mut self, interpreter: &mut Interpreter, line: i32, reason: PauseReason) -> RuntimeResult<()> {
+        let reason = if reason == PauseReason::Breakpoint { " (breakpoint)" } else { "" };
+        let location = format!("Paused at line {}{}.\n{}", line, reason, self.source_line(line, false));
+        let output_failed = |err| RuntimeError::output_failed(line, err);
+        self.write(&location).map_err(output_failed)?;
+
+        loop {
+            write!(self.output, "(debug) ").and_then(|_| self.output.flush()).map_err(output_failed)?;
+            let mut input = String::new();
+            if self.input.read_line(&mut input).unwrap_or(0) == 0 {
+                // Without anyone to take commands, let the program run to completion.
+                self.stepper.clear_breakpoints();
+                self.stepper.resume();
+                return Ok(());
+            }
+
+            let input = input.trim();
+            let (command, argument) = match input.find(' ') {
+                Some(index) => (&input[..index], input[index..].trim()),
+                None => (input, ""),
+            };
+            let response = match command {
+                "s" | "step" => {
+                    self.stepper.step_in();
+                    return Ok(());
+                }
+                "n" | "next" => {
+                    self.stepper.step_over(interpreter);
+                    return Ok(());
+                }
+                "o" | "out" => {
+                    self.stepper.step_out(interpreter);
+                    return Ok(());
+                }
+                "c" | "continue" => {
+                    self.stepper.resume();
+                    return Ok(());
+                }
+                "q" | "quit" => {
+                    let token = Token::new(TokenType::EOF, "", Literal::Nil, line, 0);
+                    return Err(RuntimeError::new(&token, "Program stopped by the debugger."));
+                }
+                "b" | "break" if argument.is_empty() => self.list_breakpoints(),
+                "b" | "break" => self.set_breakpoint(argument, true),
+                "d" | "delete" => self.set_breakpoint(argument, false),
+                "v" | "vars" => show_scopes(interpreter),
+                "p" | "print" => match evaluate(interpreter, argument) {
+                    Ok(value) => describe(&value),
+                    Err(message) => format!("Error: {}", message),
+                },
+                "l" | "list" => self.list(line),
+                "h" | "help" => HELP.to_owned(),
+                "" => continue,
+                _ => format!("Unknown command '{}'. Type 'help' for a list of commands.", command),
+            };
+            self.write(&response).map_err(output_failed)?;
+        }
+    }
+
+    fn write(&mut self, text: &str) -> io::Result<()> {
+        writeln!(self.output, "{}", text)
+    }
+
+    fn source_line(&self, line: i32, current: bool) -> String {
+        let text = self.lines.get((line - 1) as usize).map_or("", String::as_str);
+        format!("{}{:>4} | {}", if current { "->" } else { "  " }, line, text)
+    }
+
+    fn list(&self, current: i32) -> String {
+        let first = (current - 2).max(1);
+        let last = (current + 2).min(self.lines.len() as i32);
+        let lines: Vec<String> = (first..=last).map(|line| self.source_line(line, line == current)).collect();
+        lines.join("\n")
+    }
+
+    fn list_breakpoints(&self) -> String {
+        if self.stepper.breakpoints().is_empty() {
+            return "No breakpoints.".to_owned();
+        }
+        let lines: Vec<String> = self.stepper.breakpoints().iter().map(|&line| self.source_line(line, false)).collect();
+        lines.join("\n")
+    }
+
+    fn set_breakpoint(&mut self, argument: &str, enabled: bool) -> String {
+        let line = match argument.parse::<i32>() {
+            Ok(line) if line >= 1 => line,
+            _ => return format!("Expected a line number but got '{}'.", argument),
+        };
+        if enabled {
+            self.stepper.add_breakpoint(line);
+            format!("Breakpoint set on line {}.", line)
+        } else if self.stepper.remove_breakpoint(line) {
+            format!("Breakpoint on line {} removed.", line)
+        } else {
+            format!("No breakpoint on line {}.", line)
+        }
+    }
+}
+
+impl<R: BufRead, W: Write> ExecutionHook for Debugger<R, W> {
+    fn before_statement(&mut self, interpreter: &mut Interpreter, stmt: &Stmt) -> RuntimeResult<()> {
+        match self.stepper.check(interpreter, stmt) {
+            Some((line, reason)) => self.pause(interpreter, line, reason),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Formats a value for display while debugging, quoting strings so that they
+/// can be told apart from other values.
+pub fn describe(value: &LoxObject) -> String {
+    match value.as_str() {
+        Some(s) => format!("{:?}", s),
+        None => value.to_string(),
+    }
+}
+
+/// The variables of each scope from the innermost out to the globals, as
+/// `Interpreter::scopes` returns them but without the natives, which are always
+/// there and would drown out the program's own globals.
+pub fn scope_variables(interpreter: &Interpreter) -> Vec<Vec<(String, LoxObject)>> {
+    let mut scopes = interpreter.scopes();
+    if let Some(globals) = scopes.last_mut() {
+        globals.retain(|variable| !matches!(variable.1, LoxObject::Function(_)));
+    }
+    scopes
+}
+
+fn show_scopes(interpreter: &Interpreter) -> String {
+    let scopes = scope_variables(interpreter);
+    let mut lines = Vec::new();
+    for (i, scope) in scopes.iter().enumerate() {
+        let is_global = i == scopes.len() - 1;
+        lines.push(if is_global { "globals:".to_owned() } else { format!("scope {}:", i) });
+        if scope.is_empty() {
+            lines.push("  (none)".to_owned());
+        }
+        for (name, value) in scope {
+            lines.push(format!("  {} = {}", name, describe(value)));
+        }
+    }
+    lines.join("\n")
+}
+
+/// Evaluates an expression typed by the user in the interpreter's current scope.
+pub fn evaluate(interpreter: &mut Interpreter, source: &str) -> Result<LoxObject, String> {
+    let mut scanner = Scanner::new(source.to_owned());
+    let tokens = scanner.scan_tokens();
+    if let Some(err) = scanner.errors().first() {
+        return Err(err.message.to_owned());
+    }
+    let expr = Parser::new(tokens).parse_expression().map_err(|err| err.message)?;
+    interpreter.evaluate_expression(&expr).map_err(|err| err.message)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+    use lox::Lox;
+    use output::OutputBuffer;
+
+    fn debug(source: &str, commands: &str) -> (String, String) {
+        let program_output = OutputBuffer::new();
+        let debugger_output = OutputBuffer::new();
+        let mut lox = Lox::new();
+        lox.set_output(program_output.clone());
+        lox.set_hook(Debugger::new(source, Cursor::new(commands.to_owned()), debugger_output.clone()));
+        lox.run(source).expect("program should run");
+        (program_output.contents(), debugger_output.contents())
+    }
+
+    #[test]
+    fn test_breakpoints_and_inspection() {
+        let source = "var a = 1;\n{\n  var b = a + 1;\n  print b;\n}\nprint a;";
+        let (output, debugger) = debug(source, "break 4\ncontinue\nvars\nprint b * 10\nc\n");
+        assert_eq!(output, "2\n1\n");
+        assert!(debugger.starts_with("Paused at line 1.\n     1 | var a = 1;\n"));
+        assert!(debugger.contains("Paused at line 4 (breakpoint).\n     4 |   print b;\n"));
+        assert!(debugger.contains("scope 0:\n  b = 2\nglobals:\n  a = 1\n"));
+        assert!(debugger.contains("(debug) 20\n"));
+        assert!(!debugger.contains("line 6"));
+    }
+
+    #[test]
+    fn test_next_steps_over_nested_statements() {
+        let source = "var i = 0;\nwhile (i < 3)\n  i = i + 1;\n{\n  print i;\n}";
+        let (_, stepped) = debug(source, "s\ns\ns\ns\nc\n");
+        assert!(stepped.contains("Paused at line 3."));
+        let (output, nexted) = debug(source, "n\nn\nn\nc\n");
+        assert_eq!(output, "3\n");
+        assert!(!nexted.contains("Paused at line 3."));
+        assert!(nexted.contains("Paused at line 5."));
+    }
+
+    #[test]
+    fn test_quit_stops_the_program() {
+        let source = "print 1;\nprint 2;";
+        let mut lox = Lox::new();
+        lox.set_output(OutputBuffer::new());
+        lox.set_hook(Debugger::new(source, Cursor::new("s\nq\n"), OutputBuffer::new()));
+        assert!(lox.run(source).is_err());
+    }
+}
@@ -1,66 +1,115 @@
-use std::cell::RefCell;
 use std::collections::HashMap;
-use std::rc::Rc;
+use std::mem;
 
+use heap::EnvRef;
 use lox_object::LoxObject;
-use scanner::Token;
-use interpreter::{RuntimeError, RuntimeResult};
+use symbol::Symbol;
 
+/// The variables of one scope. Environments live on the interpreter's `Heap`
+/// and refer to the scope enclosing them by handle. The globals are looked up
+/// by name, while a block's locals sit in the slots the resolver gave them,
+/// kept alongside their names for the debugger.
 pub struct Environment {
-    enclosing: Option<Rc<Environment>>,
-    values: RefCell<HashMap<String, LoxObject>>,
+    enclosing: Option<EnvRef>,
+    values: HashMap<Symbol, LoxObject>,
+    slots: Vec<(Symbol, LoxObject)>,
 }
 
 impl Environment {
     pub fn new() -> Self {
         Self {
             enclosing: None,
-            values: RefCell::new(HashMap::new()),
+            values: HashMap::new(),
+            slots: Vec::new(),
         }
     }
 
-    pub fn new_enclosed(enclosing: Rc<Self>) -> Self {
+    pub fn new_enclosed(enclosing: EnvRef) -> Self {
         Self {
             enclosing: Some(enclosing),
-            values: RefCell::new(HashMap::new()),
+            values: HashMap::new(),
+            slots: Vec::new(),
         }
     }
 
-    pub fn define(&self, name: &str, value: &LoxObject) {
-        self.values.borrow_mut().insert(name.to_owned(), value.clone());
+    /// Defines a variable by name, returning the value it replaced if it was
+    /// already defined in this scope.
+    pub fn define(&mut self, name: &Symbol, value: &LoxObject) -> Option<LoxObject> {
+        self.values.insert(name.clone(), value.clone())
     }
 
-    pub fn assign(&self, name: &Token, value: &LoxObject) -> RuntimeResult<()> {
-        let mut values = self.values.borrow_mut();
-
-        if values.contains_key(&name.lexeme) {
-            values.insert(name.lexeme.to_owned(), value.clone());
-            Ok(())
+    /// Defines the local in `slot`, returning the value it replaced if the
+    /// block redeclares it. Locals are defined in slot order.
+    pub fn define_slot(&mut self, slot: usize, name: &Symbol, value: &LoxObject) -> Option<LoxObject> {
+        if slot < self.slots.len() {
+            Some(mem::replace(&mut self.slots[slot], (name.clone(), value.clone())).1)
         } else {
-            if let Some(ref enclosing_env) = self.enclosing {
-                enclosing_env.assign(name, value)
-            } else {
-                Err(RuntimeError::new(
-                    name,
-                    &format!("Undefined variable '{}'.", name.lexeme),
-                ))
-            }
+            debug_assert_eq!(slot, self.slots.len(), "locals defined out of slot order");
+            self.slots.push((name.clone(), value.clone()));
+            None
         }
     }
 
-    pub fn get(&self, name: &Token) -> RuntimeResult<LoxObject> {
-        match self.values.borrow().get(&name.lexeme) {
-            Some(object) => Ok(object.clone()),
-            None => {
-                if let Some(ref env) = self.enclosing {
-                    env.get(name)
-                } else {
-                    Err(RuntimeError::new(
-                        name,
-                        &format!("Undefined variable '{}'.", name.lexeme),
-                    ))
-                }
-            }
-        }
+    /// The value of a variable defined by name in this scope.
+    pub fn get(&self, name: &Symbol) -> Option<&LoxObject> {
+        self.values.get(name)
+    }
+
+    pub fn get_slot(&self, slot: usize) -> Option<&LoxObject> {
+        self.slots.get(slot).map(|local| &local.1)
+    }
+
+    /// Assigns to a variable defined by name in this scope, returning the value
+    /// it replaced, or `None` if it isn't defined here.
+    pub fn assign(&mut self, name: &Symbol, value: &LoxObject) -> Option<LoxObject> {
+        self.values.get_mut(name).map(|old| mem::replace(old, value.clone()))
+    }
+
+    pub fn assign_slot(&mut self, slot: usize, value: &LoxObject) -> Option<LoxObject> {
+        self.slots.get_mut(slot).map(|local| mem::replace(&mut local.1, value.clone()))
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.values.keys().chain(self.slots.iter().map(|local| &local.0)).map(Symbol::to_string).collect()
+    }
+
+    /// The names of the locals in slot order.
+    pub fn slot_names(&self) -> Vec<Symbol> {
+        self.slots.iter().map(|local| local.0.clone()).collect()
+    }
+
+    /// The variables defined directly in this scope, sorted by name.
+    pub fn variables(&self) -> Vec<(String, LoxObject)> {
+        let mut variables: Vec<(String, LoxObject)> = self
+            .values
+            .iter()
+            .chain(self.slots.iter().map(|local| (&local.0, &local.1)))
+            .map(|(name, value)| (name.to_string(), value.clone()))
+            .collect();
+        variables.sort_by(|a, b| a.0.cmp(&b.0));
+        variables
     }
+
+    pub fn values(&self) -> impl Iterator<Item = &LoxObject> {
+        self.values.values().chain(self.slots.iter().map(|local| &local.1))
+    }
+
+    /// Roughly how many bytes the variables defined directly in this scope keep alive.
+    pub fn retained_size(&self) -> usize {
+        self.values
+            .iter()
+            .chain(self.slots.iter().map(|local| (&local.0, &local.1)))
+            .map(|(name, value)| retained_size(name, value))
+            .sum()
+    }
+
+    pub fn enclosing(&self) -> Option<EnvRef> {
+        self.enclosing
+    }
+}
+
+/// Roughly how many bytes a variable holding `value` keeps alive: its name,
+/// the value itself and the contents of a string.
+pub fn retained_size(name: &str, value: &LoxObject) -> usize {
+    name.len() + mem::size_of::<LoxObject>() + value.as_str().map_or(0, str::len)
 }
@@ -0,0 +1,279 @@
+use std::mem;
+
+use scanner::{Token, TokenType};
+
+const INDENT: &str = "    ";
+
+/// Reformats a token stream that still carries its comment and newline trivia
+/// (see `Scanner::scan_tokens_with_trivia`) into the canonical Lox layout: one
+/// statement per line, four space indentation, K&R braces and single spaces
+/// around binary operators. Comments are kept, and at most one blank line is
+/// preserved between statements.
+pub struct Formatter {
+    output: String,
+    indent: usize,
+    paren_depth: usize,
+    line_breaks: usize,
+    previous: Option<TokenType>,
+    previous_is_unary: bool,
+    /// Whether the tokens so far are inside the parentheses after `if`, `while`
+    /// or `for`.
+    in_header: bool,
+    /// Whether the last token ended the header of a statement with a body.
+    after_header: bool,
+    /// How many levels deeper than `indent` the current statement goes, for
+    /// unbraced bodies a trailing comment pushed onto a line of their own.
+    hanging: usize,
+    /// Whether the last trailing comment pushed a body onto the next line.
+    hung: bool,
+    /// The hanging indentation at the latest `if`, which an `else` after an
+    /// unbraced body goes back to.
+    if_hanging: usize,
+    /// How much hanging indentation went into `indent` at each open brace.
+    braces: Vec<usize>,
+}
+
+impl Default for Formatter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Formatter {
+    pub fn new() -> Self {
+        Formatter {
+            output: String::new(),
+            indent: 0,
+            paren_depth: 0,
+            line_breaks: 0,
+            previous: None,
+            previous_is_unary: false,
+            in_header: false,
+            after_header: false,
+            hanging: 0,
+            hung: false,
+            if_hanging: 0,
+            braces: Vec::new(),
+        }
+    }
+
+    pub fn format(mut self, tokens: &[Token]) -> String {
+        let mut newlines = 0;
+        let significant: Vec<(usize, &Token)> = tokens
+            .iter()
+            .filter_map(|token| match token.token_type {
+                TokenType::NEWLINE => {
+                    newlines += 1;
+                    None
+                }
+                TokenType::WHITESPACE | TokenType::EOF => None,
+                _ => {
+                    let entry = (newlines, token);
+                    newlines = 0;
+                    Some(entry)
+                }
+            })
+            .collect();
+
+        for (i, &(newlines_before, token)) in significant.iter().enumerate() {
+            let next = significant.get(i + 1).map(|&(_, next)| next.token_type);
+            self.format_token(token, newlines_before, next);
+        }
+
+        let mut output = self.output;
+        if !output.is_empty() {
+            output.push('\n');
+        }
+        output
+    }
+
+    fn format_token(&mut self, token: &Token, newlines_before: usize, next: Option<TokenType>) {
+        // Keep a blank line the author left between statements.
+        if self.line_breaks > 0 && newlines_before > 1 && token.token_type != TokenType::RIGHT_BRACE {
+            self.line_breaks = 2;
+        }
+
+        let after_header = self.after_header;
+        let hung = self.hung;
+        if token.token_type != TokenType::COMMENT {
+            self.after_header = false;
+            self.hung = false;
+        }
+
+        match token.token_type {
+            TokenType::COMMENT => {
+                if self.previous.is_some() && newlines_before == 0 {
+                    // A trailing comment stays on the line it annotates. After
+                    // the header of a statement it pushes the body onto the
+                    // next line, so the body is indented there.
+                    self.line_breaks = 0;
+                    self.output.push(' ');
+                    if after_header {
+                        self.hanging += 1;
+                        self.hung = true;
+                    }
+                } else if self.line_breaks == 0 && self.previous.is_some() {
+                    self.line_breaks = if newlines_before > 1 { 2 } else { 1 };
+                }
+                self.write(token, false);
+                self.line_breaks = 1;
+                return;
+            }
+            TokenType::LEFT_BRACE => {
+                // A block pushed onto the next line lines up with its header.
+                if hung {
+                    self.hanging -= 1;
+                }
+                self.write(token, true);
+                let hanging = mem::replace(&mut self.hanging, 0);
+                self.indent += hanging;
+                self.braces.push(hanging);
+                if next != Some(TokenType::RIGHT_BRACE) {
+                    self.indent += 1;
+                    self.line_breaks = 1;
+                }
+                return;
+            }
+            TokenType::RIGHT_BRACE => {
+                if self.previous != Some(TokenType::LEFT_BRACE) {
+                    self.indent = self.indent.saturating_sub(1);
+                    self.line_breaks = 1;
+                }
+                self.write(token, false);
+                let hanging = self.braces.pop().unwrap_or(0);
+                self.indent = self.indent.saturating_sub(hanging);
+                match next {
+                    Some(TokenType::ELSE) => self.hanging = hanging,
+                    Some(TokenType::SEMICOLON) | Some(TokenType::RIGHT_PAREN) => {}
+                    _ => self.line_breaks = 1,
+                }
+                return;
+            }
+            TokenType::LEFT_PAREN => self.paren_depth += 1,
+            TokenType::RIGHT_PAREN => {
+                self.paren_depth = self.paren_depth.saturating_sub(1);
+                if self.in_header && self.paren_depth == 0 {
+                    self.in_header = false;
+                    self.after_header = true;
+                }
+            }
+            TokenType::IF => {
+                self.in_header = true;
+                self.if_hanging = self.hanging;
+            }
+            TokenType::WHILE | TokenType::FOR => self.in_header = true,
+            TokenType::ELSE => {
+                if self.previous == Some(TokenType::SEMICOLON) {
+                    self.hanging = self.if_hanging;
+                }
+                self.after_header = true;
+            }
+            _ => {}
+        }
+
+        let space = self.space_before(token.token_type);
+        self.write(token, space);
+
+        if token.token_type == TokenType::SEMICOLON && self.paren_depth == 0 && next != Some(TokenType::ELSE) {
+            self.line_breaks = 1;
+            self.hanging = 0;
+        }
+    }
+
+    fn space_before(&self, current: TokenType) -> bool {
+        let previous = match self.previous {
+            Some(previous) => previous,
+            None => return false,
+        };
+
+        match current {
+            TokenType::RIGHT_PAREN | TokenType::COMMA | TokenType::SEMICOLON | TokenType::DOT => return false,
+            // A call.
+            TokenType::LEFT_PAREN if is_operand_end(previous) => return false,
+            _ => {}
+        }
+
+        match previous {
+            TokenType::LEFT_PAREN | TokenType::DOT | TokenType::BANG => false,
+            TokenType::MINUS => !self.previous_is_unary,
+            _ => true,
+        }
+    }
+
+    fn write(&mut self, token: &Token, space: bool) {
+        if self.line_breaks > 0 && !self.output.is_empty() {
+            for _ in 0..self.line_breaks {
+                self.output.push('\n');
+            }
+            for _ in 0..self.indent + self.hanging {
+                self.output.push_str(INDENT);
+            }
+        } else if space && self.previous.is_some() {
+            self.output.push(' ');
+        }
+        self.line_breaks = 0;
+
+        if token.token_type == TokenType::MINUS {
+            self.previous_is_unary = !self.previous.is_some_and(is_operand_end);
+        }
+        self.output.push_str(&token.lexeme);
+        self.previous = Some(token.token_type);
+    }
+}
+
+/// Whether a token can end an operand, in which case a following `-` is binary
+/// and a following `(` starts a call.
+fn is_operand_end(token_type: TokenType) -> bool {
+    matches!(
+        token_type,
+        TokenType::IDENTIFIER | TokenType::NUMBER | TokenType::STRING | TokenType::TRUE |
+        TokenType::FALSE | TokenType::NIL | TokenType::THIS | TokenType::RIGHT_PAREN
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use scanner::Scanner;
+
+    fn format(source: &str) -> String {
+        let tokens = Scanner::new(source.to_owned()).scan_tokens_with_trivia();
+        Formatter::new().format(&tokens)
+    }
+
+    #[test]
+    fn test_spacing_and_indentation() {
+        let source = "var a=-1;if(a<0){print a*-2;}else{print!(a==clock());}\nfor(var i=0;i<2;i=i+1)print i;";
+        assert_eq!(
+            format(source),
+            "var a = -1;\n\
+             if (a < 0) {\n    print a * -2;\n} else {\n    print !(a == clock());\n}\n\
+             for (var i = 0; i < 2; i = i + 1) print i;\n"
+        );
+
+        // A trailing comment after a header pushes the body onto its own line.
+        assert_eq!(format("if (a) // c\nprint a;"), "if (a) // c\n    print a;\n");
+        assert_eq!(
+            format("while (a) // c\nif (b) // d\nprint a; else // e\nprint b;\nprint c;"),
+            "while (a) // c\n    if (b) // d\n        print a; else // e\n        print b;\nprint c;\n"
+        );
+        assert_eq!(format("if (a) // c\n{ print a; }"), "if (a) // c\n{\n    print a;\n}\n");
+    }
+
+    #[test]
+    fn test_preserves_comments_and_single_blank_lines() {
+        let source = "// header\nvar a = 1; // trailing\n\n\n\n{\n  // inside\n      print a;\n\n}\n";
+        assert_eq!(
+            format(source),
+            "// header\nvar a = 1; // trailing\n\n{\n    // inside\n    print a;\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_formatting_is_idempotent() {
+        let source = "if (true) print 1; else { print --2; }\nwhile(false){}\n// done";
+        let once = format(source);
+        assert_eq!(once, "if (true) print 1; else {\n    print --2;\n}\nwhile (false) {}\n// done\n");
+        assert_eq!(format(&once), once);
+    }
+}
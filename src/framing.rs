@@ -0,0 +1,75 @@
+use std::io;
+use std::io::prelude::*;
+
+use serde_json::{self, Value};
+
+/// The largest message body accepted, so a bogus `Content-Length` can't make
+/// the server try to allocate more memory than any real request needs.
+const MAX_CONTENT_LENGTH: usize = 64 * 1024 * 1024;
+
+/// Reads the body of one message framed by a `Content-Length` header, as both
+/// the Language Server and Debug Adapter protocols send them over stdio.
+/// Returns `None` once the stream is closed.
+pub fn read_body<R: BufRead>(input: &mut R) -> io::Result<Option<Vec<u8>>> {
+    let mut content_length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            content_length = value.trim().parse::<usize>().ok();
+        }
+    }
+
+    let length = content_length
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "missing Content-Length header"))?;
+    if length > MAX_CONTENT_LENGTH {
+        let message = format!("Content-Length {} exceeds the limit of {} bytes", length, MAX_CONTENT_LENGTH);
+        return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+    }
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    Ok(Some(body))
+}
+
+/// Reads one framed message, failing if its body isn't JSON.
+pub fn read_message<R: BufRead>(input: &mut R) -> io::Result<Option<Value>> {
+    match read_body(input)? {
+        Some(body) => serde_json::from_slice(&body)
+            .map(Some)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err)),
+        None => Ok(None),
+    }
+}
+
+pub fn write_message<W: Write>(output: &mut W, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_round_trip() {
+        let mut buffer = Vec::new();
+        write_message(&mut buffer, &json!({"id": 1})).unwrap();
+        let message = read_message(&mut Cursor::new(buffer)).unwrap();
+        assert_eq!(message, Some(json!({"id": 1})));
+    }
+
+    #[test]
+    fn test_rejects_oversized_content_length() {
+        let mut input = Cursor::new("Content-Length: 99999999999999\r\n\r\n{}");
+        let err = read_message(&mut input).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,205 @@
+use std::fmt;
+
+use environment::Environment;
+use lox_object::LoxObject;
+
+/// How many environments may be allocated before the first collection.
+const INITIAL_THRESHOLD: usize = 256;
+
+/// A handle to an environment on the `Heap`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct EnvRef(usize);
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct GcStats {
+    pub collections: u64,
+    pub allocated: u64,
+    pub freed: u64,
+    /// Environments currently on the heap, reachable or not.
+    pub live: usize,
+}
+
+impl fmt::Display for GcStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "gc: {} collections, {} allocated, {} freed, {} live",
+            self.collections, self.allocated, self.freed, self.live
+        )
+    }
+}
+
+/// Owns every environment and frees the ones no longer reachable with a
+/// mark-and-sweep collector. Environments refer to each other by `EnvRef`
+/// rather than `Rc`, so cycles through them, such as a closure stored in the
+/// scope it captures, are collected like anything else.
+pub struct Heap {
+    objects: Vec<Option<Environment>>,
+    marks: Vec<bool>,
+    free: Vec<usize>,
+    next_collection: usize,
+    stress: bool,
+    stats: GcStats,
+}
+
+impl Heap {
+    pub fn new() -> Self {
+        Heap {
+            objects: Vec::new(),
+            marks: Vec::new(),
+            free: Vec::new(),
+            next_collection: INITIAL_THRESHOLD,
+            stress: false,
+            stats: GcStats::default(),
+        }
+    }
+
+    /// Collects before every allocation rather than when the heap has grown,
+    /// which flushes out handles that aren't rooted.
+    pub fn set_stress(&mut self, stress: bool) {
+        self.stress = stress;
+    }
+
+    pub fn stats(&self) -> GcStats {
+        self.stats
+    }
+
+    /// Whether the next allocation should be preceded by a collection.
+    pub fn should_collect(&self) -> bool {
+        self.stress || self.stats.live >= self.next_collection
+    }
+
+    pub fn allocate(&mut self, env: Environment) -> EnvRef {
+        self.stats.allocated += 1;
+        self.stats.live += 1;
+        match self.free.pop() {
+            Some(index) => {
+                self.objects[index] = Some(env);
+                EnvRef(index)
+            }
+            None => {
+                self.objects.push(Some(env));
+                self.marks.push(false);
+                EnvRef(self.objects.len() - 1)
+            }
+        }
+    }
+
+    /// Frees every environment that can't be reached from `roots`, either
+    /// through the scopes enclosing them or the values they hold.
+    pub fn collect(&mut self, roots: &[EnvRef]) {
+        let mut gray: Vec<EnvRef> = roots.to_vec();
+        while let Some(EnvRef(index)) = gray.pop() {
+            if self.marks[index] {
+                continue;
+            }
+            self.marks[index] = true;
+            let env = self.objects[index].as_ref().expect("reachable environment was freed");
+            gray.extend(env.enclosing());
+            for value in env.values() {
+                if let LoxObject::Function(ref callable) = *value {
+                    callable.trace(&mut gray);
+                }
+            }
+        }
+
+        for (index, marked) in self.marks.iter_mut().enumerate() {
+            if *marked {
+                *marked = false;
+            } else if self.objects[index].take().is_some() {
+                self.free.push(index);
+                self.stats.freed += 1;
+                self.stats.live -= 1;
+            }
+        }
+        self.stats.collections += 1;
+        self.next_collection = INITIAL_THRESHOLD.max(self.stats.live * 2);
+    }
+
+    pub fn env(&self, env: EnvRef) -> &Environment {
+        self.objects[env.0].as_ref().expect("environment was freed while in use")
+    }
+
+    pub fn env_mut(&mut self, env: EnvRef) -> &mut Environment {
+        self.objects[env.0].as_mut().expect("environment was freed while in use")
+    }
+
+    /// The environment `depth` scopes out from `env`.
+    pub fn ancestor(&self, env: EnvRef, depth: usize) -> EnvRef {
+        let mut env = env;
+        for _ in 0..depth {
+            env = self.env(env).enclosing().expect("resolved scope is missing");
+        }
+        env
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::rc::Rc;
+    use interpreter::{Interpreter, RuntimeResult};
+    use lox_object::Callable;
+    use native_functions::Arity;
+    use scanner::{Literal, Token};
+    use symbol::Symbol;
+
+    /// Stands in for a closure, which keeps the scope it was defined in alive.
+    struct Closure(EnvRef);
+
+    impl Callable for Closure {
+        fn arity(&self) -> Arity {
+            Arity::Fixed(0)
+        }
+
+        fn trace(&self, gray: &mut Vec<EnvRef>) {
+            gray.push(self.0);
+        }
+
+        fn call(&self, _interpreter: &mut Interpreter, _paren: &Token, _arguments: &[LoxObject]) -> RuntimeResult<LoxObject> {
+            Ok(LoxObject::Literal(Literal::Nil))
+        }
+    }
+
+    #[test]
+    fn test_collect_frees_unreachable_environments() {
+        let mut heap = Heap::new();
+        let globals = heap.allocate(Environment::new());
+        let block = heap.allocate(Environment::new_enclosed(globals));
+        let inner = heap.allocate(Environment::new_enclosed(block));
+        let dropped = heap.allocate(Environment::new_enclosed(globals));
+
+        heap.collect(&[inner]);
+        let stats = heap.stats();
+        assert_eq!((stats.collections, stats.allocated, stats.freed, stats.live), (1, 4, 1, 3));
+        assert!(heap.objects[dropped.0].is_none());
+
+        // The freed slot is reused.
+        assert_eq!(heap.allocate(Environment::new()), dropped);
+        heap.collect(&[globals]);
+        assert_eq!(heap.stats().live, 1);
+    }
+
+    #[test]
+    fn test_collect_frees_cycles() {
+        let mut heap = Heap::new();
+        let globals = heap.allocate(Environment::new());
+        let scope = heap.allocate(Environment::new_enclosed(globals));
+        heap.env_mut(scope).define(&Symbol::intern("f"), &LoxObject::Function(Rc::new(Closure(scope))));
+        heap.env_mut(globals).define(&Symbol::intern("g"), &LoxObject::Function(Rc::new(Closure(scope))));
+
+        heap.collect(&[globals]);
+        assert_eq!(heap.stats().live, 2);
+        heap.env_mut(globals).define(&Symbol::intern("g"), &LoxObject::Literal(Literal::Nil));
+        heap.collect(&[globals]);
+        assert_eq!(heap.stats().live, 1);
+    }
+
+    #[test]
+    fn test_stress_collects_before_every_allocation() {
+        let mut heap = Heap::new();
+        assert!(!heap.should_collect());
+        heap.set_stress(true);
+        assert!(heap.should_collect());
+    }
+}
@@ -1,59 +1,454 @@
+use std::cmp::Ordering;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::io::prelude::*;
+use std::mem;
+use std::ops::{Deref, DerefMut};
 use std::rc::Rc;
+use std::time::Instant;
 
-use environment::Environment;
+use environment::{self, Environment};
+use heap::{EnvRef, GcStats, Heap};
+use limits::{Limit, Limits, MAX_NESTING};
 use parser::ast::{Expr, ExprVisitor, Stmt, StmtVisitor, AST};
-use lox_object::{LoxObject, Callable};
-use native_functions::Clock;
+use parser::chain::{self, Link};
+use parser::location::{expr_line, stmt_line};
+use resolver::{Binding, Resolver};
+use lox_object::{Callable, LoxObject};
+use native_functions::{self, Arity, NativeArgs, NativeFunction};
 use scanner::{Literal, Token, TokenType};
+use symbol::Symbol;
+
+/// Observes execution one statement at a time, as a debugger does.
+pub trait ExecutionHook {
+    /// Called before `stmt` runs, with the interpreter paused in the scope the
+    /// statement will run in. Returning an error aborts the program.
+    fn before_statement(&mut self, interpreter: &mut Interpreter, stmt: &Stmt) -> RuntimeResult<()>;
+
+    /// Called after `stmt` has run, whether or not it succeeded.
+    fn after_statement(&mut self, _stmt: &Stmt) {}
+
+    /// Called before a function is called, once its arguments are evaluated.
+    fn before_call(&mut self, _callee: &dyn Callable) {}
+
+    /// Called when a function returns, whether or not it succeeded.
+    fn after_call(&mut self, _callee: &dyn Callable) {}
+
+    /// Called after `expr` evaluates to `value`, for every subexpression too.
+    /// Failing to write what it observed aborts the program.
+    fn after_expression(&mut self, _expr: &Expr, _value: &LoxObject) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Called when a `var` statement defines a variable.
+    fn on_define(&mut self, _name: &str, _value: &LoxObject) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Called when an existing variable is assigned a new value.
+    fn on_assign(&mut self, _name: &str, _value: &LoxObject) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Chains of binary and logical operators shorter than this are evaluated by
+/// recursing into their left operands, which saves collecting their links.
+const SHORT_CHAIN: usize = 16;
 
 pub struct Interpreter {
-    globals: Rc<Environment>,
-    environment: Rc<Environment>,
+    heap: Heap,
+    globals: EnvRef,
+    environment: EnvRef,
+    /// The environments enclosing the blocks being run, which are restored as
+    /// each block ends and so have to stay alive until then.
+    saved: Vec<EnvRef>,
+    output: Box<dyn Write>,
+    hook: Option<Box<dyn ExecutionHook>>,
+    depth: usize,
+    nesting: usize,
+    /// The line of the statement being run, which errors fall back to in an
+    /// expression without tokens of its own, such as a literal.
+    line: i32,
+    limits: Limits,
+    steps: u64,
+    started: Instant,
+    call_depth: usize,
+    memory: usize,
 }
 
 impl Interpreter {
     pub fn new() -> Self {
-        let globals = Rc::new(Environment::new());
+        let mut heap = Heap::new();
+        let globals = heap.allocate(Environment::new());
 
-        globals.define("clock", &LoxObject::Function(Rc::new(Clock)));
+        let mut interpreter = Interpreter {
+            heap,
+            globals,
+            environment: globals,
+            saved: Vec::new(),
+            output: Box::new(io::stdout()),
+            hook: None,
+            depth: 0,
+            nesting: 0,
+            line: 0,
+            limits: Limits::default(),
+            steps: 0,
+            started: Instant::now(),
+            call_depth: 0,
+            memory: 0,
+        };
+        native_functions::define_builtins(&mut interpreter);
+        interpreter
+    }
+
+    /// Redirects the output of `print` statements, which goes to stdout by default.
+    pub fn set_output<W: Write + 'static>(&mut self, output: W) {
+        self.output = Box::new(output);
+    }
+
+    /// Defines a global function backed by a Rust closure.
+    pub fn define_native<F, R>(&mut self, name: &str, arity: Arity, function: F)
+    where
+        F: Fn(&NativeArgs) -> RuntimeResult<R> + 'static,
+        R: Into<LoxObject>,
+    {
+        let native = NativeFunction::new(name, arity, function);
+        let globals = self.globals;
+        self.define_in(globals, None, &Symbol::intern(name), &LoxObject::Function(Rc::new(native)));
+    }
+
+    /// Bounds the work each call to `interpret` may do. A run that exceeds a
+    /// limit fails with a `RuntimeError` whose `limit` says which one.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    pub fn set_hook<H: ExecutionHook + 'static>(&mut self, hook: H) {
+        self.hook = Some(Box::new(hook));
+    }
+
+    /// How many statements enclose the one being executed, which is zero for a
+    /// statement at the top level of the program.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Roughly how many bytes the variables currently defined keep alive, which
+    /// is what the `max_memory` limit bounds.
+    pub fn memory_used(&self) -> usize {
+        self.memory
+    }
+
+    /// Makes the garbage collector run before every allocation, for testing.
+    pub fn set_gc_stress(&mut self, stress: bool) {
+        self.heap.set_stress(stress);
+    }
 
-        Interpreter {
-            globals: globals.clone(),
-            environment: globals
+    pub fn gc_stats(&self) -> GcStats {
+        self.heap.stats()
+    }
+
+    /// Runs the garbage collector now, rooted at the globals and the scopes
+    /// of the blocks being run.
+    pub fn collect_garbage(&mut self) {
+        let mut roots = vec![self.globals, self.environment];
+        roots.extend_from_slice(&self.saved);
+        self.heap.collect(&roots);
+    }
+
+    /// The variables of each scope in the current environment chain, from the
+    /// innermost scope out to the globals.
+    pub fn scopes(&self) -> Vec<Vec<(String, LoxObject)>> {
+        let mut scopes = Vec::new();
+        let mut environment = Some(self.environment);
+        while let Some(env) = environment {
+            let env = self.heap.env(env);
+            scopes.push(env.variables());
+            environment = env.enclosing();
         }
+        scopes
+    }
+
+    /// Evaluates an expression in the current scope.
+    pub fn evaluate_expression(&mut self, expr: &Expr) -> RuntimeResult<LoxObject> {
+        let mut scopes = Vec::new();
+        let mut environment = self.environment;
+        while environment != self.globals {
+            let env = self.heap.env(environment);
+            scopes.push(env.slot_names());
+            environment = env.enclosing().expect("block without an enclosing scope");
+        }
+        scopes.reverse();
+        Resolver::with_scopes(scopes).resolve_expression(expr);
+        self.evaluate(expr)
     }
 
     pub fn interpret(&mut self, ast: &AST) -> RuntimeResult<()> {
+        Resolver::new().resolve(ast);
+        self.steps = 0;
+        self.started = Instant::now();
         for statement in ast.root.iter() {
             self.execute(statement)?;
         }
         Ok(())
     }
 
-    fn execute(&mut self, stmt: &Box<Stmt>) -> RuntimeResult<()> {
-        self.visit_stmt(stmt)
+    pub fn get_global(&self, name: &str) -> Option<LoxObject> {
+        self.heap.env(self.globals).get(&Symbol::intern(name)).cloned()
     }
 
-    fn evaluate(&mut self, expr: &Box<Expr>) -> RuntimeResult<LoxObject> {
-        self.visit_expr(expr)
+    pub fn global_names(&self) -> Vec<String> {
+        self.heap.env(self.globals).names()
+    }
+
+    pub fn set_global(&mut self, name: &str, value: LoxObject) {
+        let globals = self.globals;
+        self.define_in(globals, None, &Symbol::intern(name), &value);
+    }
+
+    fn execute(&mut self, stmt: &Stmt) -> RuntimeResult<()> {
+        let line = stmt_line(stmt).unwrap_or(self.line);
+        if let Err(limit) = self.step() {
+            return Err(RuntimeError::limit_exceeded(line, limit));
+        }
+        if self.nesting >= MAX_NESTING {
+            return Err(RuntimeError::at_line(line, "Stack overflow."));
+        }
+        // The hook is taken out for the duration of the call so that it can use
+        // the interpreter without observing itself.
+        if let Some(mut hook) = self.hook.take() {
+            let result = hook.before_statement(self, stmt);
+            self.hook = Some(hook);
+            result?;
+        }
+        let enclosing_line = mem::replace(&mut self.line, line);
+        self.depth += 1;
+        self.nesting += 1;
+        let result = self.visit_stmt(stmt);
+        self.depth -= 1;
+        self.nesting -= 1;
+        self.line = enclosing_line;
+        if let Some(ref mut hook) = self.hook {
+            hook.after_statement(stmt);
+        }
+        result
     }
 
-    fn execute_block(&mut self, statements: &[Box<Stmt>], env: Environment) -> RuntimeResult<()> {
-        let previous = Rc::clone(&self.environment);
-        self.environment = Rc::new(env);
+    fn evaluate(&mut self, expr: &Expr) -> RuntimeResult<LoxObject> {
+        self.enter(expr)?;
+        self.nesting += 1;
+        let value = self.visit_expr(expr);
+        self.nesting -= 1;
+        self.leave(expr, value?)
+    }
+
+    /// Counts the step of evaluating an expression, failing if that goes past
+    /// a limit.
+    fn enter(&mut self, expr: &Expr) -> RuntimeResult<()> {
+        if let Err(limit) = self.step() {
+            return Err(RuntimeError::limit_exceeded(self.line_of(expr), limit));
+        }
+        if self.nesting >= MAX_NESTING {
+            return Err(RuntimeError::at_line(self.line_of(expr), "Stack overflow."));
+        }
+        Ok(())
+    }
+
+    /// Shows the hook the value an expression evaluated to.
+    fn leave(&mut self, expr: &Expr, value: LoxObject) -> RuntimeResult<LoxObject> {
+        if let Some(ref mut hook) = self.hook {
+            let result = hook.after_expression(expr, &value);
+            result.map_err(|err| RuntimeError::output_failed(self.line_of(expr), err))?;
+        }
+        Ok(value)
+    }
+
+    /// Evaluates the left operand of a binary or logical expression. It's on
+    /// the same level as the expression rather than nested inside it, and a
+    /// long chain of them is evaluated in a loop so that it doesn't recurse.
+    fn evaluate_left(&mut self, lhs: &Expr) -> RuntimeResult<LoxObject> {
+        self.nesting -= 1;
+        let left = if chain::length(lhs) < SHORT_CHAIN {
+            self.evaluate(lhs)
+        } else {
+            self.evaluate_chain(lhs)
+        };
+        self.nesting += 1;
+        left
+    }
+
+    /// Evaluates each link of a chain as if on its own.
+    fn evaluate_chain(&mut self, expr: &Expr) -> RuntimeResult<LoxObject> {
+        let (operand, links) = chain::split(expr);
+        let mut left = self.evaluate(operand)?;
+        for link in links {
+            self.enter(link.expr)?;
+            self.nesting += 1;
+            let value = self.apply(&link, left);
+            self.nesting -= 1;
+            left = self.leave(link.expr, value?)?;
+        }
+        Ok(left)
+    }
+
+    /// Applies the operator of a link to the value of everything to its left.
+    fn apply(&mut self, link: &Link, left: LoxObject) -> RuntimeResult<LoxObject> {
+        let token = link.operator;
+        if let Expr::Logical(..) = *link.expr {
+            let short_circuits = if token.token_type == TokenType::OR { left.is_truthy() } else { !left.is_truthy() };
+            if short_circuits {
+                return Ok(left);
+            }
+            return self.evaluate(link.right);
+        }
+        let right = self.evaluate(link.right)?;
+        self.apply_binary(left, token, right)
+    }
+
+    fn apply_binary(&mut self, left: LoxObject, token: &Token, right: LoxObject) -> RuntimeResult<LoxObject> {
+        // Check a concatenation fits before building it, so a runaway
+        // string can't exhaust the host's memory on its own.
+        if let (TokenType::PLUS, Some(l), Some(r)) = (&token.token_type, left.as_str(), right.as_str()) {
+            self.reserve(l.len() + r.len(), token.line)?;
+        }
+
+        binary(&left, token, &right)
+    }
+
+    /// The line to report an error in an expression at.
+    fn line_of(&self, expr: &Expr) -> i32 {
+        expr_line(expr).unwrap_or(self.line)
+    }
+
+    /// Counts one step of execution, failing once a limit has been exceeded.
+    fn step(&mut self) -> Result<(), Limit> {
+        self.steps += 1;
+        if let Some(max_steps) = self.limits.max_steps {
+            if self.steps > max_steps {
+                return Err(Limit::Steps(max_steps));
+            }
+        }
+        if let Some(max_wall_time) = self.limits.max_wall_time {
+            // Reading the clock on every step would noticeably slow tight loops.
+            if self.steps.is_multiple_of(256) && self.started.elapsed() > max_wall_time {
+                return Err(Limit::WallTime(max_wall_time));
+            }
+        }
+        Ok(())
+    }
+
+    /// Fails if `bytes` more memory can't be held on to without exceeding the
+    /// memory limit.
+    fn reserve(&self, bytes: usize, line: i32) -> RuntimeResult<()> {
+        if let Some(max_memory) = self.limits.max_memory {
+            if self.memory.saturating_add(bytes) > max_memory {
+                return Err(RuntimeError::limit_exceeded(line, Limit::Memory(max_memory)));
+            }
+        }
+        Ok(())
+    }
+
+    /// Defines a variable in `env`, by name or in a local slot, keeping count
+    /// of the memory it retains.
+    fn define_in(&mut self, env: EnvRef, slot: Option<usize>, name: &Symbol, value: &LoxObject) {
+        self.memory += environment::retained_size(name, value);
+        let env = self.heap.env_mut(env);
+        let old = match slot {
+            Some(slot) => env.define_slot(slot, name, value),
+            None => env.define(name, value),
+        };
+        if let Some(old) = old {
+            self.memory -= environment::retained_size(name, &old);
+        }
+    }
+
+    fn look_up_variable(&self, name: &Token, binding: Binding) -> RuntimeResult<LoxObject> {
+        let value = match binding {
+            Binding::Local { depth, slot } => self.heap.env(self.heap.ancestor(self.environment, depth)).get_slot(slot),
+            Binding::Global => self.heap.env(self.globals).get(&name.lexeme),
+        };
+        value.cloned().ok_or_else(|| undefined(name))
+    }
+
+    /// Assigns to an existing variable, returning the value it replaced.
+    fn assign_variable(&mut self, name: &Token, binding: Binding, value: &LoxObject) -> RuntimeResult<LoxObject> {
+        let old = match binding {
+            Binding::Local { depth, slot } => {
+                let env = self.heap.ancestor(self.environment, depth);
+                self.heap.env_mut(env).assign_slot(slot, value)
+            }
+            Binding::Global => self.heap.env_mut(self.globals).assign(&name.lexeme, value),
+        };
+        old.ok_or_else(|| undefined(name))
+    }
+
+    /// Puts a new environment on the heap, collecting garbage first if the
+    /// heap has grown enough.
+    fn allocate(&mut self, env: Environment) -> EnvRef {
+        if self.heap.should_collect() {
+            self.collect_garbage();
+        }
+        self.heap.allocate(env)
+    }
+
+    fn execute_block(&mut self, statements: &[Box<Stmt>], env: EnvRef) -> RuntimeResult<()> {
+        let mut scope = ScopeGuard::enter(self, env);
         for statement in statements {
-            self.execute(statement)?;
+            scope.execute(statement)?;
         }
-        self.environment = previous;
         Ok(())
     }
 }
 
+/// Swaps a new environment into the interpreter for the lifetime of the guard and
+/// restores the enclosing one when it is dropped, so an early return on a runtime
+/// error can't leave the interpreter pointing at an inner scope.
+struct ScopeGuard<'a> {
+    interpreter: &'a mut Interpreter,
+}
+
+impl<'a> ScopeGuard<'a> {
+    fn enter(interpreter: &'a mut Interpreter, env: EnvRef) -> Self {
+        let previous = mem::replace(&mut interpreter.environment, env);
+        interpreter.saved.push(previous);
+        ScopeGuard { interpreter }
+    }
+}
+
+impl<'a> Deref for ScopeGuard<'a> {
+    type Target = Interpreter;
+
+    fn deref(&self) -> &Interpreter {
+        self.interpreter
+    }
+}
+
+impl<'a> DerefMut for ScopeGuard<'a> {
+    fn deref_mut(&mut self) -> &mut Interpreter {
+        self.interpreter
+    }
+}
+
+impl<'a> Drop for ScopeGuard<'a> {
+    fn drop(&mut self) {
+        let interpreter = &mut *self.interpreter;
+        interpreter.memory -= interpreter.heap.env(interpreter.environment).retained_size();
+        interpreter.environment = interpreter.saved.pop().expect("scope guard without a saved environment");
+    }
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl StmtVisitor<RuntimeResult<()>> for Interpreter {
-    fn visit_stmt(&mut self, stmt: &Box<Stmt>) -> RuntimeResult<()> {
-        match **stmt {
+    fn visit_stmt(&mut self, stmt: &Stmt) -> RuntimeResult<()> {
+        match *stmt {
             Stmt::Block(ref statements) => {
-                let enclosed_env = Environment::new_enclosed(Rc::clone(&self.environment));
+                let enclosed_env = self.allocate(Environment::new_enclosed(self.environment));
                 self.execute_block(statements, enclosed_env)?;
                 Ok(())
             }
@@ -63,7 +458,7 @@ impl StmtVisitor<RuntimeResult<()>> for Interpreter {
                 Ok(())
             }
 
-            Stmt::If(ref condition, ref then_clause, ref maybe_else_clause) => {
+            Stmt::If(_, ref condition, ref then_clause, ref maybe_else_clause) => {
                 if self.evaluate(condition)?.is_truthy() {
                     self.execute(then_clause)?
                 } else if let Some(ref else_clause) = *maybe_else_clause {
@@ -72,22 +467,30 @@ impl StmtVisitor<RuntimeResult<()>> for Interpreter {
                 Ok(())
             }
 
-            Stmt::Print(ref expr) => {
+            Stmt::Print(ref keyword, ref expr) => {
                 let value = self.evaluate(expr)?;
-                println!("{}", value);
+                writeln!(self.output, "{}", value).map_err(|err| RuntimeError::output_failed(keyword.line, err))?;
                 Ok(())
             }
 
-            Stmt::While(ref condition, ref body) => {
+            Stmt::While(_, ref condition, ref body) => {
                 while self.evaluate(condition)?.is_truthy() {
                     self.execute(body)?
                 }
                 Ok(())
             }
 
-            Stmt::Var(ref name, ref initializer) => {
+            Stmt::Var(ref name, ref initializer, ref binding) => {
                 let value = self.evaluate(initializer)?;
-                Rc::clone(&self.environment).define(&name.lexeme, &value);
+                self.reserve(environment::retained_size(&name.lexeme, &value), name.line)?;
+                let (env, slot) = match binding.get() {
+                    Binding::Global => (self.globals, None),
+                    Binding::Local { slot, .. } => (self.environment, Some(slot)),
+                };
+                self.define_in(env, slot, &name.lexeme, &value);
+                if let Some(ref mut hook) = self.hook {
+                    hook.on_define(&name.lexeme, &value).map_err(|err| RuntimeError::output_failed(name.line, err))?;
+                }
                 Ok(())
             }
         }
@@ -95,12 +498,19 @@ impl StmtVisitor<RuntimeResult<()>> for Interpreter {
 }
 
 impl ExprVisitor<RuntimeResult<LoxObject>> for Interpreter {
-    fn visit_expr(&mut self, expr: &Box<Expr>) -> RuntimeResult<LoxObject> {
-        match **expr {
-            Expr::Literal(ref literal) => Ok(literal.clone().to_lox_object()),
+    fn visit_expr(&mut self, expr: &Expr) -> RuntimeResult<LoxObject> {
+        match *expr {
+            Expr::Literal(ref literal) => {
+                // The optimizer folds concatenations into literals, so a
+                // string literal is checked to fit like one.
+                if let Literal::String(ref string) = *literal {
+                    self.reserve(string.len(), self.line)?;
+                }
+                Ok(literal.clone().to_lox_object())
+            }
 
             Expr::Logical(ref lhs, ref token, ref rhs) => {
-                let left = self.evaluate(lhs)?;
+                let left = self.evaluate_left(lhs)?;
 
                 if token.token_type == TokenType::OR {
                     if left.is_truthy() {
@@ -116,25 +526,9 @@ impl ExprVisitor<RuntimeResult<LoxObject>> for Interpreter {
             }
 
             Expr::Binary(ref lhs, ref token, ref rhs) => {
-                let left = self.evaluate(lhs)?;
+                let left = self.evaluate_left(lhs)?;
                 let right = self.evaluate(rhs)?;
-
-                match token.token_type {
-                    TokenType::MINUS => minus(&left, &right, &token),
-                    TokenType::SLASH => slash(&left, &right, &token),
-                    TokenType::STAR => star(&left, &right, &token),
-                    TokenType::PLUS => plus(&left, &right, &token),
-                    TokenType::GREATER => greater(&left, &right, &token),
-                    TokenType::GREATER_EQUAL => greater_equal(&left, &right, &token),
-                    TokenType::LESS => less(&left, &right, &token),
-                    TokenType::LESS_EQUAL => less_equal(&left, &right, &token),
-                    TokenType::BANG_EQUAL => Ok(Literal::Boolean(!is_equal(&left, &right)).to_lox_object()),
-                    TokenType::EQUAL_EQUAL => Ok(Literal::Boolean(is_equal(&left, &right)).to_lox_object()),
-                    _ => Err(RuntimeError::new(
-                        token,
-                        "Unrecognized token for Binary operation.",
-                    )),
-                }
+                self.apply_binary(left, token, right)
             }
 
             Expr::Call(ref callee, ref paren, ref arguments) => {
@@ -143,7 +537,7 @@ impl ExprVisitor<RuntimeResult<LoxObject>> for Interpreter {
                     _ => return Err(RuntimeError::new(paren, "Can only call functions and classes."))
                 };
 
-                if arguments.len() != function.arity() {
+                if !function.arity().accepts(arguments.len()) {
                     return Err(RuntimeError::new(
                         paren,
                         &format!("Expected {} arguments but got {}.", function.arity(), arguments.len())
@@ -155,33 +549,42 @@ impl ExprVisitor<RuntimeResult<LoxObject>> for Interpreter {
                     evaluated_args.push(self.evaluate(argument)?);
                 }
 
-                function.call(self, &evaluated_args)
+                if let Some(max_call_depth) = self.limits.max_call_depth {
+                    if self.call_depth >= max_call_depth {
+                        return Err(RuntimeError::limit_exceeded(paren.line, Limit::CallDepth(max_call_depth)));
+                    }
+                }
+
+                if let Some(ref mut hook) = self.hook {
+                    hook.before_call(&*function);
+                }
+                self.call_depth += 1;
+                let result = function.call(self, paren, &evaluated_args);
+                self.call_depth -= 1;
+                if let Some(ref mut hook) = self.hook {
+                    hook.after_call(&*function);
+                }
+                result
             }
 
             Expr::Unary(ref token, ref e) => {
                 let right = self.evaluate(e)?;
-                match token.token_type {
-                    TokenType::BANG => Ok(Literal::Boolean(!right.is_truthy()).to_lox_object()),
-                    TokenType::MINUS => {
-                        match right {
-                            LoxObject::Literal(Literal::Number(n)) => Ok(Literal::Number(-n).to_lox_object()),
-                            _ => Err(RuntimeError::new(token, "Operand must be a number.")),
-                        }
-                    }
-                    _ => Err(RuntimeError::new(
-                        token,
-                        "Unrecognized token for Unary operation.",
-                    )),
-                }
+                unary(token, &right)
             }
 
             Expr::Grouping(ref e) => self.evaluate(e),
 
-            Expr::Variable(ref name) => self.environment.get(name),
+            Expr::Variable(ref name, ref binding) => self.look_up_variable(name, binding.get()),
 
-            Expr::Assign(ref name, ref value) => {
+            Expr::Assign(ref name, ref value, ref binding) => {
                 let value = self.evaluate(value)?;
-                self.environment.assign(name, &value)?;
+                let size = environment::retained_size(&name.lexeme, &value);
+                self.reserve(size, name.line)?;
+                let old = self.assign_variable(name, binding.get(), &value)?;
+                self.memory = self.memory + size - environment::retained_size(&name.lexeme, &old);
+                if let Some(ref mut hook) = self.hook {
+                    hook.on_assign(&name.lexeme, &value).map_err(|err| RuntimeError::output_failed(name.line, err))?;
+                }
                 Ok(value)
             }
         }
@@ -190,9 +593,14 @@ impl ExprVisitor<RuntimeResult<LoxObject>> for Interpreter {
 
 pub type RuntimeResult<T> = Result<T, RuntimeError>;
 
+#[derive(Debug)]
 pub struct RuntimeError {
     pub token: Token,
     pub message: String,
+    /// The limit that stopped the program, if that is why it failed.
+    pub limit: Option<Limit>,
+    /// The error writing the program's output, or a hook's, if that is why it failed.
+    pub io: Option<io::Error>,
 }
 
 impl RuntimeError {
@@ -200,51 +608,226 @@ impl RuntimeError {
         RuntimeError {
             token: token.clone(),
             message: message.to_owned(),
+            limit: None,
+            io: None,
+        }
+    }
+
+    /// An error for output that couldn't be written, which a host sees as an
+    /// I/O error rather than a failure of the program itself.
+    pub fn output_failed(line: i32, err: io::Error) -> Self {
+        RuntimeError {
+            io: Some(err),
+            ..RuntimeError::at_line(line, "Could not write output.")
+        }
+    }
+
+    /// An error that isn't caused by any one token, reported on its line.
+    fn at_line(line: i32, message: &str) -> Self {
+        let token = Token::new(TokenType::EOF, "", Literal::Nil, line, 0);
+        RuntimeError::new(&token, message)
+    }
+
+    fn limit_exceeded(line: i32, limit: Limit) -> Self {
+        RuntimeError {
+            limit: Some(limit),
+            ..RuntimeError::at_line(line, &limit.to_string())
         }
     }
 }
 
-fn is_equal(left: &LoxObject, right: &LoxObject) -> bool {
-    left == right
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}\n[line {}]", self.message, self.token.line)
+    }
+}
+
+impl Error for RuntimeError {}
+
+fn undefined(name: &Token) -> RuntimeError {
+    RuntimeError::new(name, &format!("Undefined variable '{}'.", name.lexeme))
+}
+
+/// Applies a binary operator to operands that have already been evaluated.
+pub fn binary(left: &LoxObject, token: &Token, right: &LoxObject) -> RuntimeResult<LoxObject> {
+    match token.token_type {
+        TokenType::MINUS => minus(left, right, token),
+        TokenType::SLASH => slash(left, right, token),
+        TokenType::TILDE_SLASH => integer_divide(left, right, token),
+        TokenType::PERCENT => modulo(left, right, token),
+        TokenType::STAR => star(left, right, token),
+        TokenType::PLUS => plus(left, right, token),
+        TokenType::GREATER => greater(left, right, token),
+        TokenType::GREATER_EQUAL => greater_equal(left, right, token),
+        TokenType::LESS => less(left, right, token),
+        TokenType::LESS_EQUAL => less_equal(left, right, token),
+        TokenType::BANG_EQUAL => Ok(Literal::Boolean(!is_equal(left, right)).to_lox_object()),
+        TokenType::EQUAL_EQUAL => Ok(Literal::Boolean(is_equal(left, right)).to_lox_object()),
+        _ => Err(RuntimeError::new(
+            token,
+            "Unrecognized token for Binary operation.",
+        )),
+    }
+}
+
+/// Applies a unary operator to an operand that has already been evaluated.
+pub fn unary(token: &Token, right: &LoxObject) -> RuntimeResult<LoxObject> {
+    match token.token_type {
+        TokenType::BANG => Ok(Literal::Boolean(!right.is_truthy()).to_lox_object()),
+        TokenType::MINUS => {
+            match *right {
+                LoxObject::Literal(Literal::Integer(n)) => integer(n.checked_neg(), token),
+                LoxObject::Literal(Literal::Number(n)) => Ok(Literal::Number(-n).to_lox_object()),
+                _ => Err(RuntimeError::new(token, "Operand must be a number.")),
+            }
+        }
+        _ => Err(RuntimeError::new(
+            token,
+            "Unrecognized token for Unary operation.",
+        )),
+    }
+}
+
+/// The operands of an arithmetic operator or comparison. Two integers stay
+/// integers, but an integer with a float is promoted to a float.
+enum Operands {
+    Integers(i64, i64),
+    Floats(f64, f64),
+}
+
+impl Operands {
+    fn floats(self) -> (f64, f64) {
+        match self {
+            Operands::Integers(l, r) => (l as f64, r as f64),
+            Operands::Floats(l, r) => (l, r),
+        }
+    }
+}
+
+fn number_operands(left: &LoxObject, right: &LoxObject) -> Option<Operands> {
+    match (left, right) {
+        (&LoxObject::Literal(Literal::Integer(l)), &LoxObject::Literal(Literal::Integer(r))) => Some(Operands::Integers(l, r)),
+        _ => Some(Operands::Floats(left.as_number()?, right.as_number()?)),
+    }
 }
 
 fn get_number_operands(
     left: &LoxObject,
     right: &LoxObject,
     token: &Token,
-) -> RuntimeResult<(f64, f64)> {
+) -> RuntimeResult<Operands> {
+    number_operands(left, right).ok_or_else(|| RuntimeError::new(token, "Operands must be numbers."))
+}
+
+/// The result of checked integer arithmetic, which fails if it overflowed.
+fn integer(result: Option<i64>, token: &Token) -> RuntimeResult<LoxObject> {
+    match result {
+        Some(n) => Ok(Literal::Integer(n).to_lox_object()),
+        None => Err(RuntimeError::new(token, "Integer overflow.")),
+    }
+}
+
+fn is_equal(left: &LoxObject, right: &LoxObject) -> bool {
     match (left, right) {
-        (&LoxObject::Literal(Literal::Number(l)), &LoxObject::Literal(Literal::Number(r))) => Ok((l, r)),
-        _ => Err(RuntimeError::new(token, "Operands must be numbers.")),
+        // An integer equals the float with the same value.
+        (&LoxObject::Literal(Literal::Integer(i)), &LoxObject::Literal(Literal::Number(f))) |
+        (&LoxObject::Literal(Literal::Number(f)), &LoxObject::Literal(Literal::Integer(i))) => {
+            compare_integer_to_float(i, f) == Some(Ordering::Equal)
+        }
+        _ => match number_operands(left, right) {
+            Some(Operands::Floats(l, r)) => l == r,
+            _ => left == right,
+        },
     }
 }
 
+/// How two numbers are ordered, or `None` if either is NaN.
+fn compare(left: &LoxObject, right: &LoxObject, token: &Token) -> RuntimeResult<Option<Ordering>> {
+    let ordering = match (left, right) {
+        (&LoxObject::Literal(Literal::Integer(l)), &LoxObject::Literal(Literal::Number(r))) => {
+            compare_integer_to_float(l, r)
+        }
+        (&LoxObject::Literal(Literal::Number(l)), &LoxObject::Literal(Literal::Integer(r))) => {
+            compare_integer_to_float(r, l).map(Ordering::reverse)
+        }
+        _ => match get_number_operands(left, right, token)? {
+            Operands::Integers(l, r) => Some(l.cmp(&r)),
+            Operands::Floats(l, r) => l.partial_cmp(&r),
+        },
+    };
+    Ok(ordering)
+}
+
+/// Compares an integer with a float exactly. Converting the integer to a float
+/// instead would round it once it's past 2^53, where floats can no longer hold
+/// every integer.
+fn compare_integer_to_float(i: i64, f: f64) -> Option<Ordering> {
+    // The bounds of i64 are powers of two, so they're exact as floats.
+    if f.is_nan() {
+        None
+    } else if f >= i64::MAX as f64 {
+        Some(Ordering::Less)
+    } else if f < i64::MIN as f64 {
+        Some(Ordering::Greater)
+    } else {
+        // Within those bounds, the integral part of the float fits in an i64.
+        let whole = f.trunc();
+        Some(i.cmp(&(whole as i64)).then(whole.partial_cmp(&f)?))
+    }
+}
 
 fn minus(left: &LoxObject, right: &LoxObject, token: &Token) -> RuntimeResult<LoxObject> {
-    let (l, r) = get_number_operands(left, right, token)?;
-    Ok(Literal::Number(l - r).to_lox_object())
+    match get_number_operands(left, right, token)? {
+        Operands::Integers(l, r) => integer(l.checked_sub(r), token),
+        Operands::Floats(l, r) => Ok(Literal::Number(l - r).to_lox_object()),
+    }
 }
 
+/// Divides as floats, even when both operands are integers.
 fn slash(left: &LoxObject, right: &LoxObject, token: &Token) -> RuntimeResult<LoxObject> {
-    let (l, r) = get_number_operands(left, right, token)?;
+    let (l, r) = get_number_operands(left, right, token)?.floats();
     if r == 0.0 {
         return Err(RuntimeError::new(token, "Divide by zero error."));
     }
     Ok(Literal::Number(l / r).to_lox_object())
 }
 
+/// Divides, truncating the quotient toward zero.
+fn integer_divide(left: &LoxObject, right: &LoxObject, token: &Token) -> RuntimeResult<LoxObject> {
+    match get_number_operands(left, right, token)? {
+        Operands::Integers(_, 0) => Err(RuntimeError::new(token, "Divide by zero error.")),
+        Operands::Integers(l, r) => integer(l.checked_div(r), token),
+        Operands::Floats(_, 0.0) => Err(RuntimeError::new(token, "Divide by zero error.")),
+        Operands::Floats(l, r) => Ok(Literal::Number((l / r).trunc()).to_lox_object()),
+    }
+}
+
+/// The remainder of `~/`, which takes the sign of the left operand.
+fn modulo(left: &LoxObject, right: &LoxObject, token: &Token) -> RuntimeResult<LoxObject> {
+    match get_number_operands(left, right, token)? {
+        Operands::Integers(_, 0) => Err(RuntimeError::new(token, "Divide by zero error.")),
+        // Only `i64::MIN % -1` wraps, and its remainder is 0 all the same.
+        Operands::Integers(l, r) => Ok(Literal::Integer(l.wrapping_rem(r)).to_lox_object()),
+        Operands::Floats(_, 0.0) => Err(RuntimeError::new(token, "Divide by zero error.")),
+        Operands::Floats(l, r) => Ok(Literal::Number(l % r).to_lox_object()),
+    }
+}
+
 fn star(left: &LoxObject, right: &LoxObject, token: &Token) -> RuntimeResult<LoxObject> {
-    let (l, r) = get_number_operands(left, right, token)?;
-    Ok(Literal::Number(l * r).to_lox_object())
+    match get_number_operands(left, right, token)? {
+        Operands::Integers(l, r) => integer(l.checked_mul(r), token),
+        Operands::Floats(l, r) => Ok(Literal::Number(l * r).to_lox_object()),
+    }
 }
 
 fn plus(left: &LoxObject, right: &LoxObject, token: &Token) -> RuntimeResult<LoxObject> {
-    match (left, right) {
-        (&LoxObject::Literal(Literal::Number(l)), &LoxObject::Literal(Literal::Number(r))) => Ok(Literal::Number(l + r).to_lox_object()),
-        (&LoxObject::Literal(Literal::String(ref l)), &LoxObject::Literal(Literal::String(ref r))) => {
-            Ok(Literal::String(format!("{}{}", l, r)).to_lox_object())
-        }
-        _ => Err(RuntimeError::new(
+    if let (Some(l), Some(r)) = (left.as_str(), right.as_str()) {
+        return Ok(Literal::String(format!("{}{}", l, r).into()).to_lox_object());
+    }
+    match number_operands(left, right) {
+        Some(Operands::Integers(l, r)) => integer(l.checked_add(r), token),
+        Some(Operands::Floats(l, r)) => Ok(Literal::Number(l + r).to_lox_object()),
+        None => Err(RuntimeError::new(
             token,
             "Operands must be two numbers or two strings.",
         )),
@@ -252,21 +835,223 @@ fn plus(left: &LoxObject, right: &LoxObject, token: &Token) -> RuntimeResult<Lox
 }
 
 fn greater(left: &LoxObject, right: &LoxObject, token: &Token) -> RuntimeResult<LoxObject> {
-    let (l, r) = get_number_operands(left, right, token)?;
-    Ok(LoxObject::Literal(Literal::Boolean(l > r)))
+    let result = matches!(compare(left, right, token)?, Some(Ordering::Greater));
+    Ok(LoxObject::Literal(Literal::Boolean(result)))
 }
 
 fn greater_equal(left: &LoxObject, right: &LoxObject, token: &Token) -> RuntimeResult<LoxObject> {
-    let (l, r) = get_number_operands(left, right, token)?;
-    Ok(LoxObject::Literal(Literal::Boolean(l >= r)))
+    let result = matches!(compare(left, right, token)?, Some(Ordering::Greater) | Some(Ordering::Equal));
+    Ok(LoxObject::Literal(Literal::Boolean(result)))
 }
 
 fn less(left: &LoxObject, right: &LoxObject, token: &Token) -> RuntimeResult<LoxObject> {
-    let (l, r) = get_number_operands(left, right, token)?;
-    Ok(LoxObject::Literal(Literal::Boolean(l < r)))
+    let result = matches!(compare(left, right, token)?, Some(Ordering::Less));
+    Ok(LoxObject::Literal(Literal::Boolean(result)))
 }
 
 fn less_equal(left: &LoxObject, right: &LoxObject, token: &Token) -> RuntimeResult<LoxObject> {
-    let (l, r) = get_number_operands(left, right, token)?;
-    Ok(LoxObject::Literal(Literal::Boolean(l <= r)))
+    let result = matches!(compare(left, right, token)?, Some(Ordering::Less) | Some(Ordering::Equal));
+    Ok(LoxObject::Literal(Literal::Boolean(result)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+    use optimizer::Optimizer;
+    use parser;
+
+    fn run(interpreter: &mut Interpreter, source: &str) -> RuntimeResult<()> {
+        interpreter.interpret(&parser::parse(source))
+    }
+
+    fn get(interpreter: &Interpreter, name: &str) -> Option<LoxObject> {
+        interpreter.get_global(name)
+    }
+
+    #[test]
+    fn test_error_in_block_restores_environment() {
+        let mut interpreter = Interpreter::new();
+        let result = run(&mut interpreter, "var a = 1; { var a = 2; a / 0; }");
+        assert!(result.is_err());
+        assert_eq!(interpreter.environment, interpreter.globals);
+        assert!(get(&interpreter, "a") == Some(Literal::Integer(1).to_lox_object()));
+    }
+
+    #[test]
+    fn test_error_in_nested_blocks_restores_environment() {
+        let mut interpreter = Interpreter::new();
+        let source = "var a = \"global\"; { var b = 1; { var c = 2; { -\"oops\"; } } }";
+        assert!(run(&mut interpreter, source).is_err());
+        assert_eq!(interpreter.environment, interpreter.globals);
+        assert!(get(&interpreter, "b").is_none());
+        assert!(get(&interpreter, "c").is_none());
+
+        run(&mut interpreter, "{ var b = a; a = b + \"!\"; }").expect("should run after error");
+        assert!(get(&interpreter, "a") == Some(Literal::String("global!".into()).to_lox_object()));
+    }
+
+    #[test]
+    fn test_error_in_loop_body_restores_environment() {
+        let mut interpreter = Interpreter::new();
+        let source = "var i = 0; for (var j = 0; j < 3; j = j + 1) { i = i + 1; if (j == 1) nil + 1; }";
+        assert!(run(&mut interpreter, source).is_err());
+        assert_eq!(interpreter.environment, interpreter.globals);
+        assert!(get(&interpreter, "j").is_none());
+        assert!(get(&interpreter, "i") == Some(Literal::Integer(2).to_lox_object()));
+    }
+
+    #[test]
+    fn test_compares_integers_with_floats_exactly() {
+        assert_eq!(compare_integer_to_float(1 << 53, 9007199254740992.0), Some(Ordering::Equal));
+        assert_eq!(compare_integer_to_float((1 << 53) + 1, 9007199254740992.0), Some(Ordering::Greater));
+        assert_eq!(compare_integer_to_float(i64::MAX, 9223372036854775808.0), Some(Ordering::Less));
+        assert_eq!(compare_integer_to_float(i64::MIN, -9223372036854775808.0), Some(Ordering::Equal));
+        assert_eq!(compare_integer_to_float(i64::MIN, f64::NEG_INFINITY), Some(Ordering::Greater));
+        assert_eq!(compare_integer_to_float(-3, -3.5), Some(Ordering::Greater));
+        assert_eq!(compare_integer_to_float(0, f64::NAN), None);
+        assert!(!is_equal(&Literal::Integer(0).to_lox_object(), &Literal::Number(f64::NAN).to_lox_object()));
+    }
+
+    #[test]
+    fn test_step_limit() {
+        let mut interpreter = Interpreter::new();
+        interpreter.set_limits(Limits { max_steps: Some(1000), ..Limits::default() });
+        let err = run(&mut interpreter, "var i = 0;\nwhile (true) {\n  i = i + 1;\n}").unwrap_err();
+        assert_eq!(err.limit, Some(Limit::Steps(1000)));
+        assert!(get(&interpreter, "i").unwrap().as_number().unwrap() > 10.0);
+        assert_eq!(interpreter.environment, interpreter.globals);
+
+        // Each run gets a fresh budget.
+        assert!(run(&mut interpreter, "i = 0;").is_ok());
+    }
+
+    #[test]
+    fn test_wall_time_limit() {
+        let mut interpreter = Interpreter::new();
+        let max_wall_time = Duration::from_millis(20);
+        interpreter.set_limits(Limits { max_wall_time: Some(max_wall_time), ..Limits::default() });
+        let err = run(&mut interpreter, "while (true) {}").unwrap_err();
+        assert_eq!(err.limit, Some(Limit::WallTime(max_wall_time)));
+        assert_eq!(err.message, "Exceeded the time limit of 20ms.");
+    }
+
+    #[test]
+    fn test_folded_expressions_report_the_line_of_their_statement() {
+        let ast = Optimizer::new().optimize(parser::parse("var a = 1;\nprint 1 +\n  2;"));
+        let mut interpreter = Interpreter::new();
+        interpreter.set_limits(Limits { max_steps: Some(3), ..Limits::default() });
+        let err = interpreter.interpret(&ast).unwrap_err();
+        assert_eq!(err.limit, Some(Limit::Steps(3)));
+        assert_eq!(err.token.line, 2);
+    }
+
+    #[test]
+    fn test_call_depth_limit() {
+        let mut interpreter = Interpreter::new();
+        interpreter.set_limits(Limits { max_call_depth: Some(0), ..Limits::default() });
+        let err = run(&mut interpreter, "print 1;\nclock();").unwrap_err();
+        assert_eq!(err.limit, Some(Limit::CallDepth(0)));
+        assert_eq!(err.token.line, 2);
+
+        let err = run(&mut interpreter, "1 / \"a\";").unwrap_err();
+        assert_eq!(err.limit, None);
+    }
+
+    #[test]
+    fn test_memory_limit() {
+        let mut interpreter = Interpreter::new();
+        interpreter.set_limits(Limits { max_memory: Some(4096), ..Limits::default() });
+        let source = "var s = \"ab\";\nwhile (true) {\n  s = s + s;\n}";
+        let err = run(&mut interpreter, source).unwrap_err();
+        assert_eq!(err.limit, Some(Limit::Memory(4096)));
+        assert_eq!(err.token.line, 3);
+        assert!(interpreter.memory_used() <= 4096);
+    }
+
+    #[test]
+    fn test_folded_concatenations_count_against_the_memory_limit() {
+        let half = format!("\"{}\"", "a".repeat(1024));
+        let source = format!("var a = {};\nprint {} + {};", half, half, half);
+        for &optimize in &[false, true] {
+            let ast = parser::parse(&source);
+            let ast = if optimize { Optimizer::new().optimize(ast) } else { ast };
+            let mut interpreter = Interpreter::new();
+            let max_memory = interpreter.memory_used() + 2048;
+            interpreter.set_limits(Limits { max_memory: Some(max_memory), ..Limits::default() });
+            let err = interpreter.interpret(&ast).unwrap_err();
+            assert_eq!(err.limit, Some(Limit::Memory(max_memory)));
+            assert_eq!(err.token.line, 2);
+        }
+    }
+
+    #[test]
+    fn test_memory_is_released_when_scopes_end() {
+        let mut interpreter = Interpreter::new();
+        let baseline = interpreter.memory_used();
+        run(&mut interpreter, "var a = \"12345\";").expect("should run");
+        let with_a = interpreter.memory_used();
+        assert!(with_a > baseline);
+
+        run(&mut interpreter, "{ var b = a + a; { var c = b + b; } }").expect("should run");
+        assert_eq!(interpreter.memory_used(), with_a);
+        run(&mut interpreter, "a = \"1\";").expect("should run");
+        assert_eq!(interpreter.memory_used(), with_a - 4);
+
+        interpreter.set_limits(Limits { max_memory: Some(with_a + 1024), ..Limits::default() });
+        let source = "var i = 0; while (i < 1000) { var s = \"some text\"; i = i + 1; }";
+        run(&mut interpreter, source).expect("short-lived strings should fit");
+    }
+
+    #[test]
+    fn test_garbage_collection_frees_finished_blocks() {
+        let mut interpreter = Interpreter::new();
+        let source = "var i = 0; while (i < 1000) { var a = i; { var b = a; } i = i + 1; }";
+        run(&mut interpreter, source).expect("should run");
+        let stats = interpreter.gc_stats();
+        assert_eq!(stats.allocated, 2001);
+        assert!(stats.collections > 0);
+        assert!(stats.live <= 256);
+
+        interpreter.collect_garbage();
+        assert_eq!(interpreter.gc_stats().live, 1);
+    }
+
+    #[test]
+    fn test_gc_stress_keeps_active_scopes() {
+        let mut interpreter = Interpreter::new();
+        interpreter.set_gc_stress(true);
+        let source = "var a = 1; { var b = 2; { var c = 3; { var d = a + b + c; a = d; } } }";
+        run(&mut interpreter, source).expect("should run");
+        assert_eq!(interpreter.gc_stats().collections, 3);
+        assert!(get(&interpreter, "a") == Some(Literal::Integer(6).to_lox_object()));
+    }
+
+    #[test]
+    fn test_string_literals_share_interned_text() {
+        let mut interpreter = Interpreter::new();
+        run(&mut interpreter, "var a = \"text\"; var b = \"text\"; var c = a + \"\";").expect("should run");
+        let text = |name| match get(&interpreter, name) {
+            Some(LoxObject::Literal(Literal::String(s))) => s,
+            _ => panic!("expected a string"),
+        };
+        assert!(Rc::ptr_eq(&text("a"), &text("b")));
+        assert!(Rc::ptr_eq(&text("a"), Symbol::intern("text").as_rc()));
+        assert!(!Rc::ptr_eq(&text("a"), &text("c")));
+        assert!(text("a") == text("c"));
+    }
+
+    #[test]
+    fn test_integer_arithmetic_overflow() {
+        let mut interpreter = Interpreter::new();
+        run(&mut interpreter, "var min = -9223372036854775807 - 1; var r = min % -1;").expect("should run");
+        assert!(get(&interpreter, "r") == Some(LoxObject::from(0)));
+        for source in &["-min;", "min ~/ -1;", "min - 1;", "min * 2;", "4611686018427387904 * 2;"] {
+            let err = run(&mut interpreter, source).unwrap_err();
+            assert_eq!(err.message, "Integer overflow.", "{}", source);
+        }
+        // Mixing in a float promotes the whole operation, which can't overflow.
+        run(&mut interpreter, "var f = min * 2.0;").expect("should run");
+        assert!(get(&interpreter, "f") == Some(LoxObject::from(-18446744073709551616.0)));
+    }
 }
@@ -0,0 +1,44 @@
+#[macro_use]
+extern crate lazy_static;
+#[macro_use]
+extern crate serde_json;
+
+mod lox;
+mod scanner;
+mod parser;
+mod dap;
+mod debugger;
+mod environment;
+mod formatter;
+mod framing;
+mod heap;
+mod interpreter;
+mod limits;
+mod linter;
+mod lsp;
+mod lox_object;
+mod native_functions;
+mod optimizer;
+mod output;
+mod profiler;
+mod resolver;
+mod symbol;
+mod tracer;
+
+pub use lox::{Lox, LoxError, LoxResult, SyntaxError};
+pub use parser::ast::{Expr, ExprVisitor, Stmt, StmtVisitor, AST};
+pub use parser::ast_printer::AstPrinter;
+pub use dap::DebugAdapter;
+pub use debugger::Debugger;
+pub use heap::{EnvRef, GcStats};
+pub use interpreter::{ExecutionHook, Interpreter, RuntimeError, RuntimeResult};
+pub use limits::{Limit, Limits};
+pub use linter::{Lint, LINTS};
+pub use lsp::server::LanguageServer;
+pub use lox_object::{Callable, LoxObject};
+pub use native_functions::{Arity, FromLoxObject, NativeArgs, NativeFunction};
+pub use output::OutputBuffer;
+pub use profiler::{Profiler, ProfileStats};
+pub use scanner::{Literal, Token, TokenType};
+pub use symbol::Symbol;
+pub use tracer::Tracer;
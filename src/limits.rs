@@ -0,0 +1,42 @@
+use std::fmt;
+use std::time::Duration;
+
+/// How deeply statements and expressions may nest, in both the parser and the
+/// interpreter. Both recurse on the native stack, and this keeps them well clear
+/// of overflowing the usual 8 MiB main thread stack, even in a debug build.
+pub const MAX_NESTING: usize = 256;
+
+/// Bounds on how much work a single run of the interpreter may do, for hosts
+/// that execute untrusted scripts. Every limit is off by default.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Limits {
+    /// The number of statements and expressions that may be evaluated.
+    pub max_steps: Option<u64>,
+    /// How long a run may take, measured from when it starts.
+    pub max_wall_time: Option<Duration>,
+    /// How many calls may be in progress at once.
+    pub max_call_depth: Option<usize>,
+    /// How many bytes the program's variables may hold on to at once, counting
+    /// strings by their length.
+    pub max_memory: Option<usize>,
+}
+
+/// The limit a run stopped at, carried by the `RuntimeError` it fails with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Limit {
+    Steps(u64),
+    WallTime(Duration),
+    CallDepth(usize),
+    Memory(usize),
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Limit::Steps(max) => write!(f, "Exceeded the limit of {} steps.", max),
+            Limit::WallTime(max) => write!(f, "Exceeded the time limit of {}ms.", max.as_millis()),
+            Limit::CallDepth(max) => write!(f, "Exceeded the call depth limit of {}.", max),
+            Limit::Memory(max) => write!(f, "Exceeded the memory limit of {} bytes.", max),
+        }
+    }
+}
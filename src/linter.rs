@@ -0,0 +1,293 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use parser::ast::{Expr, ExprVisitor, Stmt, StmtVisitor, AST};
+use parser::chain;
+use parser::location::stmt_line;
+use scanner::{Literal, Token};
+
+pub const UNUSED_VARIABLE: &str = "unused_variable";
+pub const UNDECLARED_ASSIGNMENT: &str = "undeclared_assignment";
+pub const UNREACHABLE_CODE: &str = "unreachable_code";
+pub const CONSTANT_CONDITION: &str = "constant_condition";
+pub const SHADOWING: &str = "shadowing";
+
+/// Every lint the linter knows about, any of which can be disabled by name.
+pub const LINTS: &[&str] = &[
+    UNUSED_VARIABLE,
+    UNDECLARED_ASSIGNMENT,
+    UNREACHABLE_CODE,
+    CONSTANT_CONDITION,
+    SHADOWING,
+];
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Lint {
+    pub name: &'static str,
+    pub line: i32,
+    pub message: String,
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[line {}] Warning: {} [{}]", self.line, self.message, self.name)
+    }
+}
+
+struct Local {
+    token: Token,
+    used: bool,
+}
+
+/// A static pass over the AST that reports suspicious but legal code.
+pub struct Linter {
+    scopes: Vec<HashMap<String, Local>>,
+    globals: HashSet<String>,
+    disabled: HashSet<String>,
+    lints: Vec<Lint>,
+}
+
+impl Linter {
+    /// Creates a linter that treats `predefined` names, such as natives, as
+    /// already declared globals.
+    pub fn new<I: IntoIterator<Item = String>>(predefined: I) -> Self {
+        Linter {
+            scopes: Vec::new(),
+            globals: predefined.into_iter().collect(),
+            disabled: HashSet::new(),
+            lints: Vec::new(),
+        }
+    }
+
+    pub fn disable(&mut self, name: &str) {
+        self.disabled.insert(name.to_owned());
+    }
+
+    pub fn lint(mut self, ast: &AST) -> Vec<Lint> {
+        self.lint_statements(&ast.root);
+        self.lints.sort_by_key(|lint| lint.line);
+        self.lints
+    }
+
+    fn report(&mut self, name: &'static str, line: i32, message: String) {
+        if !self.disabled.contains(name) {
+            self.lints.push(Lint { name, line, message });
+        }
+    }
+
+    /// Lints a statement list, returning whether control can reach its end.
+    fn lint_statements(&mut self, statements: &[Box<Stmt>]) -> bool {
+        let mut reachable = true;
+        for (i, stmt) in statements.iter().enumerate() {
+            if !self.visit_stmt(stmt) && reachable {
+                reachable = false;
+                if let Some(next) = statements.get(i + 1) {
+                    let line = stmt_line(next).or_else(|| stmt_line(stmt)).unwrap_or(0);
+                    self.report(UNREACHABLE_CODE, line, "Unreachable code.".to_owned());
+                }
+            }
+        }
+        reachable
+    }
+
+    fn begin_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    fn end_scope(&mut self) {
+        if let Some(scope) = self.scopes.pop() {
+            let mut unused: Vec<&Local> = scope.values().filter(|local| !local.used).collect();
+            unused.sort_by_key(|local| local.token.line);
+            let lints: Vec<(i32, String)> = unused
+                .iter()
+                .map(|local| (local.token.line, format!("Local variable '{}' is never used.", local.token.lexeme)))
+                .collect();
+            for (line, message) in lints {
+                self.report(UNUSED_VARIABLE, line, message);
+            }
+        }
+    }
+
+    fn declare(&mut self, name: &Token) {
+        let shadows = {
+            let (current, enclosing) = match self.scopes.split_last() {
+                Some((current, enclosing)) => (Some(current), enclosing),
+                None => (None, &[][..]),
+            };
+            let redeclared = current.is_some_and(|scope| scope.contains_key(name.lexeme.as_str()));
+            !redeclared && current.is_some() &&
+                (enclosing.iter().any(|scope| scope.contains_key(name.lexeme.as_str())) || self.globals.contains(name.lexeme.as_str()))
+        };
+        if shadows {
+            self.report(
+                SHADOWING,
+                name.line,
+                format!("Variable '{}' shadows a variable in an enclosing scope.", name.lexeme),
+            );
+        }
+
+        match self.scopes.last_mut() {
+            Some(scope) => {
+                scope.insert(name.lexeme.to_string(), Local { token: name.clone(), used: false });
+            }
+            None => {
+                self.globals.insert(name.lexeme.to_string());
+            }
+        }
+    }
+
+    fn resolve(&mut self, name: &Token, is_read: bool) -> bool {
+        for scope in self.scopes.iter_mut().rev() {
+            if let Some(local) = scope.get_mut(name.lexeme.as_str()) {
+                local.used = local.used || is_read;
+                return true;
+            }
+        }
+        self.globals.contains(name.lexeme.as_str())
+    }
+
+    fn check_constant_condition(&mut self, keyword: &Token, condition: &Expr) {
+        if let Some(literal) = constant(condition) {
+            let truthy = literal.clone().to_lox_object().is_truthy();
+            self.report(
+                CONSTANT_CONDITION,
+                keyword.line,
+                format!("Condition of '{}' is always {}.", keyword.lexeme, truthy),
+            );
+        }
+    }
+}
+
+/// Each visit returns whether control can continue past the statement.
+impl StmtVisitor<bool> for Linter {
+    fn visit_stmt(&mut self, stmt: &Stmt) -> bool {
+        match *stmt {
+            Stmt::Block(ref statements) => {
+                self.begin_scope();
+                let reachable = self.lint_statements(statements);
+                self.end_scope();
+                reachable
+            }
+            Stmt::Expression(ref expr) | Stmt::Print(_, ref expr) => {
+                self.visit_expr(expr);
+                true
+            }
+            Stmt::If(ref keyword, ref condition, ref then_branch, ref maybe_else_branch) => {
+                self.check_constant_condition(keyword, condition);
+                self.visit_expr(condition);
+                let then_reachable = self.visit_stmt(then_branch);
+                match *maybe_else_branch {
+                    Some(ref else_branch) => self.visit_stmt(else_branch) || then_reachable,
+                    None => true,
+                }
+            }
+            Stmt::Var(ref name, ref initializer, _) => {
+                self.visit_expr(initializer);
+                self.declare(name);
+                true
+            }
+            Stmt::While(ref keyword, ref condition, ref body) => {
+                let always_true = constant(condition).map(|literal| literal.clone().to_lox_object().is_truthy());
+                // `while (true)` is the usual way to spell an endless loop, so only
+                // a loop whose body can never run is worth a warning.
+                if always_true == Some(false) {
+                    self.check_constant_condition(keyword, condition);
+                }
+                self.visit_expr(condition);
+                self.visit_stmt(body);
+                // There is no `break` or `return`, so a loop whose condition is
+                // always true can only be left through a runtime error.
+                always_true != Some(true)
+            }
+        }
+    }
+}
+
+impl ExprVisitor<()> for Linter {
+    fn visit_expr(&mut self, expr: &Expr) {
+        match *expr {
+            Expr::Assign(ref name, ref value, _) => {
+                self.visit_expr(value);
+                if !self.resolve(name, false) {
+                    self.report(
+                        UNDECLARED_ASSIGNMENT,
+                        name.line,
+                        format!("Assignment to undeclared variable '{}'.", name.lexeme),
+                    );
+                }
+            }
+            Expr::Literal(_) => {}
+            Expr::Logical(..) | Expr::Binary(..) => {
+                let (operand, links) = chain::split(expr);
+                self.visit_expr(operand);
+                for link in links {
+                    self.visit_expr(link.right);
+                }
+            }
+            Expr::Call(ref callee, _, ref arguments) => {
+                self.visit_expr(callee);
+                for argument in arguments {
+                    self.visit_expr(argument);
+                }
+            }
+            Expr::Unary(_, ref e) | Expr::Grouping(ref e) => self.visit_expr(e),
+            Expr::Variable(ref name, _) => {
+                self.resolve(name, true);
+            }
+        }
+    }
+}
+
+fn constant(expr: &Expr) -> Option<&Literal> {
+    match *expr {
+        Expr::Literal(ref literal) => Some(literal),
+        Expr::Grouping(ref e) => constant(e),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use native_functions::BUILTIN_NAMES;
+    use parser;
+
+    fn lint(source: &str, disabled: &[&str]) -> Vec<(&'static str, i32)> {
+        let ast = parser::parse(source);
+        let mut linter = Linter::new(BUILTIN_NAMES.iter().map(|name| name.to_string()));
+        for name in disabled {
+            linter.disable(name);
+        }
+        linter.lint(&ast).iter().map(|lint| (lint.name, lint.line)).collect()
+    }
+
+    #[test]
+    fn test_unused_and_shadowed_locals() {
+        let source = "var a = 1;\n{\n  var a = 2;\n  var b = a;\n  print b;\n}\n{ var c = clock(); c = 1; }";
+        assert_eq!(lint(source, &[]), vec![(SHADOWING, 3), (UNUSED_VARIABLE, 7)]);
+        assert_eq!(lint(source, &[SHADOWING]), vec![(UNUSED_VARIABLE, 7)]);
+    }
+
+    #[test]
+    fn test_undeclared_assignment() {
+        let source = "a = 1;\nvar b;\nb = 2;\n{ var c; c = 3; print c; }";
+        assert_eq!(lint(source, &[]), vec![(UNDECLARED_ASSIGNMENT, 1)]);
+    }
+
+    #[test]
+    fn test_unreachable_code_and_constant_conditions() {
+        let source = "if (true) print 1;\nwhile (false) print 2;\nwhile (true) { print 3; }\nprint clock();";
+        assert_eq!(
+            lint(source, &[]),
+            vec![(CONSTANT_CONDITION, 1), (CONSTANT_CONDITION, 2), (UNREACHABLE_CODE, 4)]
+        );
+        assert_eq!(lint(source, &[CONSTANT_CONDITION, UNREACHABLE_CODE]), vec![]);
+    }
+
+    #[test]
+    fn test_scope_example_shadowing() {
+        let source = include_str!("../examples/scope.lox");
+        let lints = lint(source, &[]);
+        assert_eq!(lints, vec![(SHADOWING, 5), (SHADOWING, 6), (SHADOWING, 8)]);
+    }
+}
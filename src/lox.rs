@@ -1,86 +1,393 @@
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
 use std::io;
 use std::io::prelude::*;
-use std::fs::File;
-use std::process;
+use std::path::Path;
 
-use scanner::{Scanner, Token, TokenType};
-use parser::parser::Parser;
-use interpreter::{Interpreter, RuntimeError};
+use scanner::{Scanner, ScanError, Token, TokenType};
+use parser::ast::AST;
+use parser::ast_printer::AstPrinter;
+use parser::parser::{Parser, ParseError};
+use interpreter::{ExecutionHook, Interpreter, RuntimeError, RuntimeResult};
+use formatter::Formatter;
+use heap::GcStats;
+use limits::Limits;
+use linter::{Lint, Linter};
+use optimizer::Optimizer;
+use lox_object::LoxObject;
+use native_functions::{Arity, NativeArgs};
 
+/// An embeddable Lox interpreter. Globals defined by one call to `run` remain
+/// visible to the next, so a host can evaluate several snippets in the same session.
 pub struct Lox {
-    had_error: bool,
-    had_runtime_error: bool,
+    interpreter: Interpreter,
+    error_output: Box<dyn Write>,
+    optimize: bool,
 }
 
 impl Lox {
     pub fn new() -> Self {
         Lox {
-            had_error: false,
-            had_runtime_error: false,
+            interpreter: Interpreter::new(),
+            error_output: Box::new(io::stderr()),
+            optimize: false,
         }
     }
 
-    pub fn run_file(&mut self, file_name: &str) {
-        let mut f = File::open(file_name).unwrap();
+    /// Redirects the output of `print` statements, which goes to stdout by default.
+    pub fn set_output<W: Write + 'static>(&mut self, output: W) {
+        self.interpreter.set_output(output);
+    }
+
+    /// Redirects the sink used by `report`, which is stderr by default.
+    pub fn set_error_output<W: Write + 'static>(&mut self, output: W) {
+        self.error_output = Box::new(output);
+    }
+
+    /// Folds constant expressions and removes dead branches before running a
+    /// program or dumping its syntax tree.
+    pub fn set_optimize(&mut self, optimize: bool) {
+        self.optimize = optimize;
+    }
+
+    /// Bounds the work each call to `run` may do, for untrusted scripts.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.interpreter.set_limits(limits);
+    }
+
+    /// Roughly how many bytes the program's variables currently keep alive.
+    pub fn memory_used(&self) -> usize {
+        self.interpreter.memory_used()
+    }
+
+    /// Makes the garbage collector run before every allocation, for testing.
+    pub fn set_gc_stress(&mut self, stress: bool) {
+        self.interpreter.set_gc_stress(stress);
+    }
+
+    pub fn gc_stats(&self) -> GcStats {
+        self.interpreter.gc_stats()
+    }
+
+    /// Installs a hook, such as a `Debugger`, that runs before every statement.
+    pub fn set_hook<H: ExecutionHook + 'static>(&mut self, hook: H) {
+        self.interpreter.set_hook(hook);
+    }
+
+    /// Writes an error returned by `run` or `run_file` to the error sink.
+    pub fn report(&mut self, err: &LoxError) -> io::Result<()> {
+        writeln!(self.error_output, "{}", err)
+    }
+
+    pub fn run_file<P: AsRef<Path>>(&mut self, path: P) -> LoxResult<()> {
+        let mut f = File::open(path)?;
         let mut buffer = String::new();
-        f.read_to_string(&mut buffer).unwrap();
-        self.run(buffer);
-        if self.had_error {
-            process::exit(65);
+        f.read_to_string(&mut buffer)?;
+        self.run(&buffer)
+    }
+
+    pub fn run(&mut self, source: &str) -> LoxResult<()> {
+        let ast = self.parse_for_running(source)?;
+        self.interpreter.interpret(&ast)?;
+        Ok(())
+    }
+
+    /// Scans and parses a source string without running it.
+    pub fn parse(&self, source: &str) -> LoxResult<AST> {
+        let mut scanner = Scanner::new(source.to_owned());
+        let tokens = scanner.scan_tokens();
+        let errors: Vec<SyntaxError> = scanner.errors().iter().map(SyntaxError::from).collect();
+        self.parse_tokens_after(tokens, errors)
+    }
+
+    /// Parses a token stream that didn't come from scanning a source string,
+    /// such as one made by a tool. The stream is ended with an `EOF` token if
+    /// it doesn't already have one.
+    pub fn parse_tokens(&self, tokens: Vec<Token>) -> LoxResult<AST> {
+        self.parse_tokens_after(tokens, Vec::new())
+    }
+
+    /// Parses a token stream, failing with any errors from scanning it as well
+    /// as those from parsing it.
+    fn parse_tokens_after(&self, tokens: Vec<Token>, mut errors: Vec<SyntaxError>) -> LoxResult<AST> {
+        let mut parser = Parser::new(tokens);
+        match parser.parse() {
+            Ok(ast) => {
+                if errors.is_empty() {
+                    Ok(ast)
+                } else {
+                    Err(LoxError::Syntax(errors))
+                }
+            }
+            Err(parse_errors) => {
+                errors.extend(parse_errors.iter().map(SyntaxError::from));
+                Err(LoxError::Syntax(errors))
+            }
         }
-        if self.had_runtime_error {
-            process::exit(70);
+    }
+
+    /// Parses a source string and optimizes it if that's enabled.
+    fn parse_for_running(&self, source: &str) -> LoxResult<AST> {
+        let ast = self.parse(source)?;
+        if self.optimize {
+            Ok(Optimizer::new().optimize(ast))
+        } else {
+            Ok(ast)
         }
     }
 
-    pub fn run_prompt(&mut self) {
-        loop {
-            print!("> ");
-            io::stdout().flush().unwrap();
-            let mut input = String::new();
-            io::stdin().read_line(&mut input).unwrap();
-            self.run(input);
-            self.had_error = false;
-            self.had_runtime_error = false;
+    /// Scans a source string and lists its tokens, one per line, optionally
+    /// including comment and whitespace trivia. The tokens are listed even if
+    /// scanning fails, since those around an error help to find it, and the
+    /// result says whether it did.
+    pub fn dump_tokens(&self, source: &str, include_trivia: bool) -> (String, LoxResult<()>) {
+        let mut scanner = Scanner::new(source.to_owned());
+        let tokens = if include_trivia {
+            scanner.scan_tokens_with_trivia()
+        } else {
+            scanner.scan_tokens()
+        };
+        let lines: Vec<String> = tokens
+            .iter()
+            .map(|token| {
+                let token_type = format!("{:?}", token.token_type);
+                format!("{:>4}:{:<3} {:<14} {:<16} {}", token.line, token.column, token_type, format!("{:?}", token.lexeme), token.literal)
+            })
+            .collect();
+        let result = if scanner.errors().is_empty() {
+            Ok(())
+        } else {
+            Err(LoxError::Syntax(scanner.errors().iter().map(SyntaxError::from).collect()))
+        };
+        (lines.join("\n"), result)
+    }
+
+    /// Reformats a source string into the canonical layout, keeping its comments.
+    /// The source must parse, so broken code is never reshuffled.
+    pub fn format(&self, source: &str) -> LoxResult<String> {
+        self.parse(source)?;
+        let tokens = Scanner::new(source.to_owned()).scan_tokens_with_trivia();
+        Ok(Formatter::new().format(&tokens))
+    }
+
+    /// Runs the linter over a source string, skipping the lints named in `disabled`.
+    /// Globals already defined in this interpreter count as declared.
+    pub fn lint(&self, source: &str, disabled: &[String]) -> LoxResult<Vec<Lint>> {
+        let ast = self.parse(source)?;
+        let mut linter = Linter::new(self.interpreter.global_names());
+        for name in disabled {
+            linter.disable(name);
         }
+        Ok(linter.lint(&ast))
     }
 
-    fn run(&mut self, source: String) {
-        let mut scanner = Scanner::new(source);
-        let tokens = scanner.scan_tokens();
-        let mut parser = Parser::new(tokens);
-        let ast = parser.parse();
-        let mut interpreter = Interpreter::new();
+    /// Parses a source string and renders its syntax tree as S-expressions,
+    /// as optimized if that's enabled.
+    pub fn dump_ast(&self, source: &str) -> LoxResult<String> {
+        let ast = self.parse_for_running(source)?;
+        Ok(AstPrinter::new().print(&ast))
+    }
 
-        match ast {
-            Ok(tree) => {
-                if let Err(err) = interpreter.interpret(&tree) {
-                    self.runtime_error(err)
-                }
+    pub fn get_global(&self, name: &str) -> Option<LoxObject> {
+        self.interpreter.get_global(name)
+    }
+
+    pub fn set_global<T: Into<LoxObject>>(&mut self, name: &str, value: T) {
+        self.interpreter.set_global(name, value.into());
+    }
+
+    /// Registers a Rust closure as a global Lox function, e.g.
+    /// `lox.define_native("sqrt", Arity::Fixed(1), |args| Ok(args.number(0)?.sqrt()))`.
+    pub fn define_native<F, R>(&mut self, name: &str, arity: Arity, function: F)
+    where
+        F: Fn(&NativeArgs) -> RuntimeResult<R> + 'static,
+        R: Into<LoxObject>,
+    {
+        self.interpreter.define_native(name, arity, function);
+    }
+}
+
+impl Default for Lox {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub type LoxResult<T> = Result<T, LoxError>;
+
+#[derive(Debug)]
+pub enum LoxError {
+    Io(io::Error),
+    Syntax(Vec<SyntaxError>),
+    Runtime(RuntimeError),
+}
+
+impl LoxError {
+    /// The exit code a command line driver should use for this error, following
+    /// the sysexits.h conventions used by the reference implementation.
+    pub fn exit_code(&self) -> i32 {
+        match *self {
+            LoxError::Io(_) => 74,
+            LoxError::Syntax(_) => 65,
+            LoxError::Runtime(_) => 70,
+        }
+    }
+}
+
+impl fmt::Display for LoxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LoxError::Io(ref err) => write!(f, "{}", err),
+            LoxError::Syntax(ref errors) => {
+                let lines: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+                write!(f, "{}", lines.join("\n"))
             }
-            Err(e) => self.token_error(e.token, &e.message),
+            LoxError::Runtime(ref err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl Error for LoxError {}
+
+impl From<io::Error> for LoxError {
+    fn from(err: io::Error) -> Self {
+        LoxError::Io(err)
+    }
+}
+
+impl From<RuntimeError> for LoxError {
+    fn from(mut err: RuntimeError) -> Self {
+        match err.io.take() {
+            Some(io_err) => LoxError::Io(io_err),
+            None => LoxError::Runtime(err),
         }
     }
+}
 
-    fn token_error(&mut self, token: Token, message: &str) {
-        if token.token_type == TokenType::EOF {
-            self.report(token.line, " at end", message);
+/// A scan or parse error, located at a line and optionally a token.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SyntaxError {
+    pub line: i32,
+    pub location: String,
+    pub message: String,
+}
+
+impl<'a> From<&'a ScanError> for SyntaxError {
+    fn from(err: &'a ScanError) -> Self {
+        SyntaxError {
+            line: err.line,
+            location: String::new(),
+            message: err.message.to_owned(),
+        }
+    }
+}
+
+impl<'a> From<&'a ParseError> for SyntaxError {
+    fn from(err: &'a ParseError) -> Self {
+        let location = if err.token.token_type == TokenType::EOF {
+            " at end".to_owned()
         } else {
-            self.report(token.line, &format!(" at '{}'", token.lexeme), message);
+            format!(" at '{}'", err.token.lexeme)
+        };
+        SyntaxError {
+            line: err.token.line,
+            location,
+            message: err.message.to_owned(),
         }
     }
+}
 
-    fn runtime_error(&mut self, err: RuntimeError) {
-        println!("{}\n[line {}]", err.message, err.token.line);
-        self.had_runtime_error = true;
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[line {}] Error{}: {}", self.line, self.location, self.message)
     }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use output::OutputBuffer;
 
-    fn line_error(&mut self, line: i32, message: &str) {
-        self.report(line, "", message);
+    #[test]
+    fn test_globals_round_trip() {
+        let mut lox = Lox::new();
+        lox.set_global("name", "world");
+        lox.set_global("count", 2.5);
+        lox.set_global("flag", true);
+        lox.run("var greeting = \"hello \" + name; var twice = count * 2; var negated = !flag;")
+            .expect("should run");
+        assert_eq!(lox.get_global("greeting"), Some(LoxObject::from("hello world")));
+        assert_eq!(lox.get_global("twice"), Some(LoxObject::from(5.0)));
+        assert_eq!(lox.get_global("negated"), Some(LoxObject::from(false)));
+        assert_eq!(lox.get_global("missing"), None);
+
+        // Globals stay defined from one run to the next.
+        lox.run("twice = twice + 1;").expect("should run");
+        assert_eq!(lox.get_global("twice"), Some(LoxObject::from(6.0)));
     }
 
-    fn report(&mut self, line: i32, error_location: &str, message: &str) {
-        println!("[line {}] Error {}: {}", line, error_location, message);
-        self.had_error = true;
+    #[test]
+    fn test_run_file_reports_missing_file_as_io_error() {
+        let err = Lox::new().run_file("tests/lox/does_not_exist.lox").unwrap_err();
+        match err {
+            LoxError::Io(ref io_err) => assert_eq!(io_err.kind(), io::ErrorKind::NotFound),
+            _ => panic!("expected an I/O error, got {:?}", err),
+        }
+        assert_eq!(err.exit_code(), 74);
+    }
+
+    #[test]
+    fn test_dump_tokens_places_trivia_where_it_appears() {
+        let (dump, result) = Lox::new().dump_tokens("a; // one\n  b;\n", true);
+        result.expect("should scan");
+        let positions: Vec<&str> = dump.lines().map(|line| line.split_whitespace().next().unwrap()).collect();
+        assert_eq!(positions, ["1:1", "1:2", "1:3", "1:4", "1:10", "2:1", "2:2", "2:3", "2:4", "2:5", "3:1"]);
+        assert!(dump.lines().nth(4).unwrap().contains("NEWLINE"));
+    }
+
+    #[test]
+    fn test_dump_tokens_lists_tokens_before_scan_errors() {
+        let (dump, result) = Lox::new().dump_tokens("print \"a;", false);
+        let token_types: Vec<&str> = dump.lines().map(|line| line.split_whitespace().nth(1).unwrap()).collect();
+        assert_eq!(token_types, ["PRINT", "EOF"]);
+        let err = result.unwrap_err();
+        assert_eq!(err.to_string(), "[line 1] Error: Unterminated string.");
+        assert_eq!(err.exit_code(), 65);
+    }
+
+    #[test]
+    fn test_long_chains_work_everywhere() {
+        let chain = format!("print 0{}; print false{};", " + 1".repeat(100_000), " or false".repeat(100_000));
+        for &optimize in &[false, true] {
+            let output = OutputBuffer::new();
+            let mut lox = Lox::new();
+            lox.set_output(output.clone());
+            lox.set_optimize(optimize);
+            lox.run(&chain).expect("should run");
+            assert_eq!(output.contents(), "100000\nfalse\n");
+        }
+        let lox = Lox::new();
+        assert!(lox.dump_ast(&chain).expect("should parse").starts_with("(print (+ (+ (+"));
+        lox.format(&chain).expect("should format");
+        lox.lint(&chain, &[]).expect("should lint");
+    }
+
+    #[test]
+    fn test_error_exit_codes() {
+        let mut lox = Lox::new();
+        let err = lox.run("print ;").unwrap_err();
+        match err {
+            LoxError::Syntax(ref errors) => assert_eq!(errors[0].message, "Expect expression."),
+            _ => panic!("expected a syntax error, got {:?}", err),
+        }
+        assert_eq!(err.exit_code(), 65);
+
+        let err = lox.run("print -\"a\";").unwrap_err();
+        match err {
+            LoxError::Runtime(ref err) => assert_eq!(err.message, "Operand must be a number."),
+            _ => panic!("expected a runtime error, got {:?}", err),
+        }
+        assert_eq!(err.exit_code(), 70);
     }
 }
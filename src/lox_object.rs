@@ -2,19 +2,21 @@ use std::cmp::PartialEq;
 use std::fmt;
 use std::rc::Rc;
 
+use heap::EnvRef;
 use interpreter::{Interpreter, RuntimeResult};
-use scanner::Literal;
+use native_functions::Arity;
+use scanner::{Literal, Token};
 
 #[derive(Clone)]
 pub enum LoxObject {
-    Function(Rc<Callable>),
+    Function(Rc<dyn Callable>),
     Literal(Literal)
 }
 
 impl LoxObject {
     pub fn is_truthy(&self) -> bool {
-        match self {
-            &LoxObject::Literal(ref literal) => match *literal {
+        match *self {
+            LoxObject::Literal(ref literal) => match *literal {
                 Literal::Nil => false,
                 Literal::Boolean(b) => b,
                 _ => true,
@@ -22,27 +24,107 @@ impl LoxObject {
             _ => false
         }
     }
+
+    /// The value of a number, with an integer converted to a float.
+    pub fn as_number(&self) -> Option<f64> {
+        match *self {
+            LoxObject::Literal(Literal::Integer(n)) => Some(n as f64),
+            LoxObject::Literal(Literal::Number(n)) => Some(n),
+            _ => None
+        }
+    }
+
+    pub fn as_integer(&self) -> Option<i64> {
+        match *self {
+            LoxObject::Literal(Literal::Integer(n)) => Some(n),
+            _ => None
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match *self {
+            LoxObject::Literal(Literal::Boolean(b)) => Some(b),
+            _ => None
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match *self {
+            LoxObject::Literal(Literal::String(ref s)) => Some(s),
+            _ => None
+        }
+    }
+
+    pub fn is_nil(&self) -> bool {
+        matches!(*self, LoxObject::Literal(Literal::Nil))
+    }
+}
+
+impl From<i64> for LoxObject {
+    fn from(n: i64) -> Self {
+        Literal::Integer(n).to_lox_object()
+    }
+}
+
+impl From<f64> for LoxObject {
+    fn from(n: f64) -> Self {
+        Literal::Number(n).to_lox_object()
+    }
+}
+
+impl From<bool> for LoxObject {
+    fn from(b: bool) -> Self {
+        Literal::Boolean(b).to_lox_object()
+    }
+}
+
+impl From<String> for LoxObject {
+    fn from(s: String) -> Self {
+        Literal::String(s.into()).to_lox_object()
+    }
+}
+
+impl<'a> From<&'a str> for LoxObject {
+    fn from(s: &'a str) -> Self {
+        Literal::String(s.into()).to_lox_object()
+    }
 }
 
 impl PartialEq for LoxObject {
     fn eq(&self, other: &Self) -> bool {
         match (self, other) {
-            (&LoxObject::Literal(ref lhs), &LoxObject::Literal(ref rhs)) => lhs == rhs,
+            (LoxObject::Literal(lhs), LoxObject::Literal(rhs)) => lhs == rhs,
             _ => false
         }
     }
 }
 
+impl fmt::Debug for LoxObject {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LoxObject::Literal(ref literal) => write!(f, "{:?}", literal),
+            LoxObject::Function(_) => write!(f, "Function")
+        }
+    }
+}
+
 impl fmt::Display for LoxObject {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-            &LoxObject::Literal(ref literal) => write!(f, "{}", literal),
-            &LoxObject::Function(_) => write!(f, "<function>")
+        match *self {
+            LoxObject::Literal(ref literal) => write!(f, "{}", literal),
+            LoxObject::Function(_) => write!(f, "<function>")
         }
     }
 }
 
 pub trait Callable {
-    fn arity(&self) -> usize;
-    fn call(&self, interpreter: &mut Interpreter, arguments: &[LoxObject]) -> RuntimeResult<LoxObject>;
+    /// The name reported by tools such as the profiler.
+    fn name(&self) -> &str {
+        "<function>"
+    }
+    fn arity(&self) -> Arity;
+    /// Pushes each environment on the heap that this callable keeps alive, such
+    /// as the one a closure captures, so the collector doesn't free it.
+    fn trace(&self, _gray: &mut Vec<EnvRef>) {}
+    fn call(&self, interpreter: &mut Interpreter, paren: &Token, arguments: &[LoxObject]) -> RuntimeResult<LoxObject>;
 }
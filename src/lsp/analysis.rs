@@ -0,0 +1,288 @@
+use std::collections::HashMap;
+
+use linter::Linter;
+use native_functions::BUILTIN_NAMES;
+use parser::ast::{Expr, ExprVisitor, Stmt, StmtVisitor};
+use parser::chain;
+use parser::parser::Parser;
+use scanner::{Scanner, Token};
+
+/// A zero-based position in a document, as used by the protocol.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Position {
+    pub line: u32,
+    pub character: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Range {
+    pub start: Position,
+    pub end: Position,
+}
+
+impl Range {
+    fn of_token(lines: &[String], token: &Token) -> Self {
+        // A token's line is the line it ends on, which only differs from the line
+        // it starts on for strings spanning several lines.
+        let newlines = token.lexeme.matches('\n').count() as u32;
+        let line = (token.line as u32).saturating_sub(1 + newlines);
+        let column = (token.column as usize).saturating_sub(1);
+        let start = Position { line, character: utf16_column(lines, line, column) };
+        let end = match token.lexeme.rfind('\n') {
+            Some(index) => Position {
+                line: line + newlines,
+                character: token.lexeme[index + 1..].encode_utf16().count() as u32,
+            },
+            None => Position {
+                line,
+                character: utf16_column(lines, line, column + token.lexeme.len()),
+            },
+        };
+        Range { start, end }
+    }
+
+    fn of_line(lines: &[String], line: i32) -> Self {
+        let index = (line as u32).saturating_sub(1);
+        let length = lines.get(index as usize).map_or(0, |text| text.len());
+        Range {
+            start: Position { line: index, character: 0 },
+            end: Position { line: index, character: utf16_column(lines, index, length) },
+        }
+    }
+
+    fn at(lines: &[String], line: i32, column: i32) -> Self {
+        let line = (line as u32).saturating_sub(1);
+        let column = (column as usize).saturating_sub(1);
+        let start = Position { line, character: utf16_column(lines, line, column) };
+        // The range covers the whole character at the column.
+        let width = lines
+            .get(line as usize)
+            .and_then(|text| text.get(column..))
+            .and_then(|rest| rest.chars().next())
+            .map_or(1, |c| c.len_utf16() as u32);
+        Range { start, end: Position { line, character: start.character + width } }
+    }
+
+    pub fn contains(&self, position: Position) -> bool {
+        self.start <= position && position < self.end
+    }
+}
+
+/// Converts a byte offset into a line to the number of UTF-16 code units
+/// before it, which is how the protocol counts characters by default.
+fn utf16_column(lines: &[String], line: u32, byte: usize) -> u32 {
+    let text = lines.get(line as usize).map_or("", String::as_str);
+    text.char_indices()
+        .take_while(|&(index, c)| index + c.len_utf8() <= byte)
+        .map(|(_, c)| c.len_utf16() as u32)
+        .sum()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    Error = 1,
+    Warning = 2,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    pub range: Range,
+    pub severity: Severity,
+    pub code: Option<&'static str>,
+    pub message: String,
+}
+
+/// A declared variable together with every place that refers to it.
+#[derive(Clone, Debug)]
+pub struct Symbol {
+    pub name: String,
+    pub declaration: Token,
+    pub references: Vec<Token>,
+    pub is_global: bool,
+}
+
+/// Everything the server knows about one version of a document.
+pub struct Analysis {
+    pub diagnostics: Vec<Diagnostic>,
+    pub symbols: Vec<Symbol>,
+    lines: Vec<String>,
+}
+
+impl Analysis {
+    pub fn new(source: &str) -> Self {
+        let lines: Vec<String> = source.lines().map(str::to_owned).collect();
+        let mut scanner = Scanner::new(source.to_owned());
+        let tokens = scanner.scan_tokens();
+        let mut diagnostics: Vec<Diagnostic> = scanner
+            .errors()
+            .iter()
+            .map(|err| Diagnostic {
+                range: Range::at(&lines, err.line, err.column),
+                severity: Severity::Error,
+                code: None,
+                message: err.message.to_owned(),
+            })
+            .collect();
+
+        let (ast, errors) = Parser::new(tokens).parse_recovering();
+        if errors.is_empty() && diagnostics.is_empty() {
+            // Lints on code with syntax errors would mostly be noise.
+            let lints = Linter::new(BUILTIN_NAMES.iter().map(|name| name.to_string())).lint(&ast);
+            diagnostics.extend(lints.into_iter().map(|lint| Diagnostic {
+                range: Range::of_line(&lines, lint.line),
+                severity: Severity::Warning,
+                code: Some(lint.name),
+                message: lint.message,
+            }));
+        }
+        diagnostics.extend(errors.into_iter().map(|err| Diagnostic {
+            range: Range::of_token(&lines, &err.token),
+            severity: Severity::Error,
+            code: None,
+            message: err.message,
+        }));
+
+        let mut resolver = Resolver::new();
+        resolver.resolve_globals(&ast.root);
+        for stmt in ast.root.iter() {
+            resolver.visit_stmt(stmt);
+        }
+
+        Analysis {
+            diagnostics,
+            symbols: resolver.symbols,
+            lines,
+        }
+    }
+
+    /// The symbol whose declaration or one of whose references covers `position`.
+    pub fn symbol_at(&self, position: Position) -> Option<&Symbol> {
+        self.symbols.iter().find(|symbol| {
+            self.range_of(&symbol.declaration).contains(position) ||
+                symbol.references.iter().any(|token| self.range_of(token).contains(position))
+        })
+    }
+
+    pub fn range_of(&self, token: &Token) -> Range {
+        Range::of_token(&self.lines, token)
+    }
+}
+
+/// Binds each variable use to its declaration, mirroring how the interpreter
+/// looks variables up: locals lexically, globals by name wherever they are declared.
+struct Resolver {
+    symbols: Vec<Symbol>,
+    scopes: Vec<HashMap<String, usize>>,
+    globals: HashMap<String, usize>,
+}
+
+impl Resolver {
+    fn new() -> Self {
+        Resolver {
+            symbols: Vec::new(),
+            scopes: Vec::new(),
+            globals: HashMap::new(),
+        }
+    }
+
+    fn resolve_globals(&mut self, statements: &[Box<Stmt>]) {
+        for stmt in statements {
+            if let Stmt::Var(ref name, _, _) = **stmt {
+                if !self.globals.contains_key(name.lexeme.as_str()) {
+                    let index = self.add_symbol(name, true);
+                    self.globals.insert(name.lexeme.to_string(), index);
+                }
+            }
+        }
+    }
+
+    fn add_symbol(&mut self, name: &Token, is_global: bool) -> usize {
+        self.symbols.push(Symbol {
+            name: name.lexeme.to_string(),
+            declaration: name.clone(),
+            references: Vec::new(),
+            is_global,
+        });
+        self.symbols.len() - 1
+    }
+
+    fn declare(&mut self, name: &Token) {
+        if self.scopes.is_empty() {
+            // Redeclaring a global redefines the same variable.
+            if let Some(&index) = self.globals.get(name.lexeme.as_str()) {
+                if self.symbols[index].declaration != *name {
+                    self.symbols[index].references.push(name.clone());
+                }
+            }
+            return;
+        }
+        let index = self.add_symbol(name, false);
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name.lexeme.to_string(), index);
+        }
+    }
+
+    fn reference(&mut self, name: &Token) {
+        let local = self.scopes.iter().rev().filter_map(|scope| scope.get(name.lexeme.as_str())).next();
+        if let Some(&index) = local.or_else(|| self.globals.get(name.lexeme.as_str())) {
+            self.symbols[index].references.push(name.clone());
+        }
+    }
+}
+
+impl StmtVisitor<()> for Resolver {
+    fn visit_stmt(&mut self, stmt: &Stmt) {
+        match *stmt {
+            Stmt::Block(ref statements) => {
+                self.scopes.push(HashMap::new());
+                for stmt in statements {
+                    self.visit_stmt(stmt);
+                }
+                self.scopes.pop();
+            }
+            Stmt::Expression(ref expr) | Stmt::Print(_, ref expr) => self.visit_expr(expr),
+            Stmt::If(_, ref condition, ref then_branch, ref maybe_else_branch) => {
+                self.visit_expr(condition);
+                self.visit_stmt(then_branch);
+                if let Some(ref else_branch) = *maybe_else_branch {
+                    self.visit_stmt(else_branch);
+                }
+            }
+            Stmt::Var(ref name, ref initializer, _) => {
+                self.visit_expr(initializer);
+                self.declare(name);
+            }
+            Stmt::While(_, ref condition, ref body) => {
+                self.visit_expr(condition);
+                self.visit_stmt(body);
+            }
+        }
+    }
+}
+
+impl ExprVisitor<()> for Resolver {
+    fn visit_expr(&mut self, expr: &Expr) {
+        match *expr {
+            Expr::Assign(ref name, ref value, _) => {
+                self.visit_expr(value);
+                self.reference(name);
+            }
+            Expr::Literal(_) => {}
+            Expr::Logical(..) | Expr::Binary(..) => {
+                let (operand, links) = chain::split(expr);
+                self.visit_expr(operand);
+                for link in links {
+                    self.visit_expr(link.right);
+                }
+            }
+            Expr::Call(ref callee, _, ref arguments) => {
+                self.visit_expr(callee);
+                for argument in arguments {
+                    self.visit_expr(argument);
+                }
+            }
+            Expr::Unary(_, ref e) | Expr::Grouping(ref e) => self.visit_expr(e),
+            Expr::Variable(ref name, _) => self.reference(name),
+        }
+    }
+}
@@ -0,0 +1,403 @@
+use std::collections::HashMap;
+use std::io;
+use std::io::prelude::*;
+
+use serde_json::{self, Value};
+
+use framing;
+use lsp::analysis::{Analysis, Diagnostic, Position, Range};
+
+const PARSE_ERROR: i64 = -32700;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const SYMBOL_KIND_VARIABLE: u32 = 13;
+
+/// A language server speaking JSON-RPC with `Content-Length` framing, as the
+/// Language Server Protocol specifies for stdio.
+pub struct LanguageServer<R, W> {
+    input: R,
+    output: W,
+    documents: HashMap<String, String>,
+    shutdown_requested: bool,
+}
+
+impl<R: BufRead, W: Write> LanguageServer<R, W> {
+    pub fn new(input: R, output: W) -> Self {
+        LanguageServer {
+            input,
+            output,
+            documents: HashMap::new(),
+            shutdown_requested: false,
+        }
+    }
+
+    /// Serves requests until the client sends `exit` or closes the stream, and
+    /// returns the process exit code the protocol asks for.
+    pub fn run(&mut self) -> io::Result<i32> {
+        while let Some(body) = framing::read_body(&mut self.input)? {
+            let message: Value = match serde_json::from_slice(&body) {
+                Ok(message) => message,
+                Err(err) => {
+                    // There's no id to answer a body that isn't JSON with,
+                    // so the error goes to no request in particular, and the
+                    // framing still says where the next message starts.
+                    self.write_message(&json!({
+                        "jsonrpc": "2.0",
+                        "id": null,
+                        "error": { "code": PARSE_ERROR, "message": format!("Parse error: {}", err) },
+                    }))?;
+                    continue;
+                }
+            };
+            let method = message["method"].as_str().unwrap_or("").to_owned();
+            if method == "exit" {
+                break;
+            }
+            let params = &message["params"];
+            match message.get("id") {
+                Some(id) => {
+                    let response = match self.handle_request(&method, params) {
+                        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+                        Err((code, error)) => json!({
+                            "jsonrpc": "2.0",
+                            "id": id,
+                            "error": { "code": code, "message": error },
+                        }),
+                    };
+                    self.write_message(&response)?;
+                }
+                None => self.handle_notification(&method, params)?,
+            }
+        }
+        Ok(if self.shutdown_requested { 0 } else { 1 })
+    }
+
+    fn handle_request(&mut self, method: &str, params: &Value) -> Result<Value, (i64, String)> {
+        match method {
+            "initialize" => Ok(json!({
+                "capabilities": {
+                    "textDocumentSync": 1,
+                    "definitionProvider": true,
+                    "referencesProvider": true,
+                    "hoverProvider": true,
+                    "documentSymbolProvider": true,
+                },
+                "serverInfo": { "name": "rlox", "version": env!("CARGO_PKG_VERSION") },
+            })),
+            "shutdown" => {
+                self.shutdown_requested = true;
+                Ok(Value::Null)
+            }
+            "textDocument/definition" => {
+                let (uri, source, position) = self.document_position(params)?;
+                let analysis = Analysis::new(source);
+                Ok(match analysis.symbol_at(position) {
+                    Some(symbol) => location(&uri, analysis.range_of(&symbol.declaration)),
+                    None => Value::Null,
+                })
+            }
+            "textDocument/references" => {
+                let (uri, source, position) = self.document_position(params)?;
+                let include_declaration = params["context"]["includeDeclaration"].as_bool().unwrap_or(true);
+                let analysis = Analysis::new(source);
+                let locations: Vec<Value> = match analysis.symbol_at(position) {
+                    Some(symbol) => {
+                        let mut tokens: Vec<_> = symbol.references.iter().collect();
+                        if include_declaration {
+                            tokens.insert(0, &symbol.declaration);
+                        }
+                        tokens.iter().map(|token| location(&uri, analysis.range_of(token))).collect()
+                    }
+                    None => Vec::new(),
+                };
+                Ok(Value::Array(locations))
+            }
+            "textDocument/hover" => {
+                let (_, source, position) = self.document_position(params)?;
+                let analysis = Analysis::new(source);
+                Ok(match analysis.symbol_at(position) {
+                    Some(symbol) => {
+                        let declaration = source
+                            .lines()
+                            .nth(symbol.declaration.line as usize - 1)
+                            .unwrap_or("")
+                            .trim();
+                        let scope = if symbol.is_global { "global" } else { "local" };
+                        json!({
+                            "contents": {
+                                "kind": "markdown",
+                                "value": format!(
+                                    "```lox\n{}\n```\n{} variable declared on line {}",
+                                    declaration,
+                                    scope,
+                                    symbol.declaration.line
+                                ),
+                            },
+                        })
+                    }
+                    None => Value::Null,
+                })
+            }
+            "textDocument/documentSymbol" => {
+                let uri = document_uri(params)?;
+                let source = self.document(&uri)?;
+                let analysis = Analysis::new(source);
+                let symbols: Vec<Value> = analysis
+                    .symbols
+                    .iter()
+                    .map(|symbol| {
+                        let range = range_json(analysis.range_of(&symbol.declaration));
+                        json!({
+                            "name": symbol.name,
+                            "detail": if symbol.is_global { "global" } else { "local" },
+                            "kind": SYMBOL_KIND_VARIABLE,
+                            "range": range,
+                            "selectionRange": range,
+                        })
+                    })
+                    .collect();
+                Ok(Value::Array(symbols))
+            }
+            _ => Err((METHOD_NOT_FOUND, format!("Unhandled method '{}'.", method))),
+        }
+    }
+
+    fn handle_notification(&mut self, method: &str, params: &Value) -> io::Result<()> {
+        let uri = match params["textDocument"]["uri"].as_str() {
+            Some(uri) => uri.to_owned(),
+            None => return Ok(()),
+        };
+        match method {
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str().unwrap_or("").to_owned();
+                self.documents.insert(uri.clone(), text);
+                self.publish_diagnostics(&uri)
+            }
+            "textDocument/didChange" => {
+                // Only full document sync is advertised, so the last change holds
+                // the whole text.
+                let changes = params["contentChanges"].as_array();
+                if let Some(text) = changes.and_then(|c| c.last()).and_then(|c| c["text"].as_str()) {
+                    self.documents.insert(uri.clone(), text.to_owned());
+                }
+                self.publish_diagnostics(&uri)
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                self.write_message(&json!({
+                    "jsonrpc": "2.0",
+                    "method": "textDocument/publishDiagnostics",
+                    "params": { "uri": uri, "diagnostics": [] },
+                }))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn publish_diagnostics(&mut self, uri: &str) -> io::Result<()> {
+        let diagnostics: Vec<Value> = match self.documents.get(uri) {
+            Some(source) => Analysis::new(source).diagnostics.iter().map(diagnostic_json).collect(),
+            None => Vec::new(),
+        };
+        self.write_message(&json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/publishDiagnostics",
+            "params": { "uri": uri, "diagnostics": diagnostics },
+        }))
+    }
+
+    fn document(&self, uri: &str) -> Result<&str, (i64, String)> {
+        self.documents
+            .get(uri)
+            .map(String::as_str)
+            .ok_or_else(|| (INVALID_PARAMS, format!("Unknown document '{}'.", uri)))
+    }
+
+    fn document_position(&self, params: &Value) -> Result<(String, &str, Position), (i64, String)> {
+        let uri = document_uri(params)?;
+        let source = self.document(&uri)?;
+        let line = params["position"]["line"].as_u64();
+        let character = params["position"]["character"].as_u64();
+        match (line, character) {
+            (Some(line), Some(character)) => {
+                let position = Position { line: line as u32, character: character as u32 };
+                Ok((uri, source, position))
+            }
+            _ => Err((INVALID_PARAMS, "Missing position.".to_owned())),
+        }
+    }
+
+    fn write_message(&mut self, message: &Value) -> io::Result<()> {
+        framing::write_message(&mut self.output, message)
+    }
+}
+
+fn document_uri(params: &Value) -> Result<String, (i64, String)> {
+    params["textDocument"]["uri"]
+        .as_str()
+        .map(str::to_owned)
+        .ok_or_else(|| (INVALID_PARAMS, "Missing document uri.".to_owned()))
+}
+
+fn range_json(range: Range) -> Value {
+    json!({
+        "start": { "line": range.start.line, "character": range.start.character },
+        "end": { "line": range.end.line, "character": range.end.character },
+    })
+}
+
+fn location(uri: &str, range: Range) -> Value {
+    json!({ "uri": uri, "range": range_json(range) })
+}
+
+fn diagnostic_json(diagnostic: &Diagnostic) -> Value {
+    let mut value = json!({
+        "range": range_json(diagnostic.range),
+        "severity": diagnostic.severity as u32,
+        "source": "rlox",
+        "message": diagnostic.message,
+    });
+    if let Some(code) = diagnostic.code {
+        value["code"] = json!(code);
+    }
+    value
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+
+    const URI: &str = "file:///test.lox";
+
+    fn frame(messages: &[Value]) -> Cursor<Vec<u8>> {
+        let mut input = Vec::new();
+        for message in messages {
+            let body = message.to_string();
+            write!(input, "Content-Length: {}\r\n\r\n{}", body.len(), body).unwrap();
+        }
+        Cursor::new(input)
+    }
+
+    fn unframe(output: Vec<u8>) -> Vec<Value> {
+        let mut reader = Cursor::new(output);
+        let mut messages = Vec::new();
+        while let Some(message) = framing::read_message(&mut reader).unwrap() {
+            messages.push(message);
+        }
+        messages
+    }
+
+    fn session(source: &str, requests: &[(&str, Value)]) -> (i32, Vec<Value>) {
+        let mut messages = vec![
+            json!({ "jsonrpc": "2.0", "id": 0, "method": "initialize", "params": {} }),
+            json!({
+                "jsonrpc": "2.0",
+                "method": "textDocument/didOpen",
+                "params": { "textDocument": { "uri": URI, "languageId": "lox", "version": 1, "text": source } },
+            }),
+        ];
+        for (i, &(method, ref params)) in requests.iter().enumerate() {
+            messages.push(json!({ "jsonrpc": "2.0", "id": i + 1, "method": method, "params": params }));
+        }
+        messages.push(json!({ "jsonrpc": "2.0", "id": 99, "method": "shutdown" }));
+        messages.push(json!({ "jsonrpc": "2.0", "method": "exit" }));
+
+        let mut output = Vec::new();
+        let exit_code = LanguageServer::new(frame(&messages), &mut output).run().unwrap();
+        (exit_code, unframe(output))
+    }
+
+    fn at(line: u32, character: u32) -> Value {
+        json!({ "textDocument": { "uri": URI }, "position": { "line": line, "character": character } })
+    }
+
+    #[test]
+    fn test_publishes_diagnostics() {
+        let (exit_code, responses) = session("print 1 +;\n{ var a = 1; }", &[]);
+        assert_eq!(exit_code, 0);
+        assert_eq!(responses[0]["result"]["capabilities"]["hoverProvider"], json!(true));
+
+        let diagnostics = &responses[1]["params"]["diagnostics"];
+        assert_eq!(responses[1]["method"], json!("textDocument/publishDiagnostics"));
+        assert_eq!(diagnostics.as_array().unwrap().len(), 1);
+        assert_eq!(diagnostics[0]["message"], json!("Expect expression."));
+        assert_eq!(diagnostics[0]["range"]["start"], json!({ "line": 0, "character": 9 }));
+        assert_eq!(diagnostics[0]["severity"], json!(1));
+    }
+
+    #[test]
+    fn test_definition_references_and_hover() {
+        let source = "var a = 1;\n{\n  var a = 2;\n  print a;\n}\na = a + 1;";
+        let (_, responses) = session(source, &[
+            ("textDocument/definition", at(3, 8)),
+            ("textDocument/references", at(0, 4)),
+            ("textDocument/hover", at(5, 0)),
+            ("textDocument/documentSymbol", json!({ "textDocument": { "uri": URI } })),
+        ]);
+
+        let definition = &responses[2]["result"];
+        assert_eq!(definition["range"]["start"], json!({ "line": 2, "character": 6 }));
+
+        let references: Vec<u64> = responses[3]["result"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|location| location["range"]["start"]["line"].as_u64().unwrap())
+            .collect();
+        assert_eq!(references, vec![0, 5, 5]);
+
+        let hover = responses[4]["result"]["contents"]["value"].as_str().unwrap();
+        assert_eq!(hover, "```lox\nvar a = 1;\n```\nglobal variable declared on line 1");
+
+        let symbols = responses[5]["result"].as_array().unwrap();
+        assert_eq!(symbols.len(), 2);
+        assert_eq!(symbols[1]["detail"], json!("local"));
+    }
+
+    #[test]
+    fn test_positions_count_utf16_code_units() {
+        // "é" is one code unit and two bytes, "😀" two code units and four bytes.
+        let source = "var s = \"é😀\"; print s;\nprint \"😀\" + 1 +;";
+        let (_, responses) = session(source, &[("textDocument/references", at(0, 21))]);
+
+        let diagnostics = &responses[1]["params"]["diagnostics"];
+        assert_eq!(diagnostics[0]["range"]["start"], json!({ "line": 1, "character": 16 }));
+
+        let ranges: Vec<&Value> = responses[2]["result"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|location| &location["range"])
+            .collect();
+        assert_eq!(ranges.len(), 2);
+        assert_eq!(ranges[1]["start"], json!({ "line": 0, "character": 21 }));
+        assert_eq!(ranges[1]["end"], json!({ "line": 0, "character": 22 }));
+    }
+
+    #[test]
+    fn test_unknown_request_and_exit_without_shutdown() {
+        let messages = vec![
+            json!({ "jsonrpc": "2.0", "id": 1, "method": "textDocument/rename", "params": {} }),
+            json!({ "jsonrpc": "2.0", "method": "exit" }),
+        ];
+        let mut output = Vec::new();
+        let exit_code = LanguageServer::new(frame(&messages), &mut output).run().unwrap();
+        assert_eq!(exit_code, 1);
+        assert_eq!(unframe(output)[0]["error"]["code"], json!(METHOD_NOT_FOUND));
+    }
+
+    #[test]
+    fn test_replies_to_a_body_that_is_not_json_and_carries_on() {
+        let mut input = b"Content-Length: 5\r\n\r\n{oops".to_vec();
+        input.extend(frame(&[json!({ "jsonrpc": "2.0", "id": 1, "method": "shutdown" })]).into_inner());
+        let mut output = Vec::new();
+        let exit_code = LanguageServer::new(Cursor::new(input), &mut output).run().unwrap();
+        assert_eq!(exit_code, 0);
+
+        let responses = unframe(output);
+        assert_eq!(responses[0]["id"], Value::Null);
+        assert_eq!(responses[0]["error"]["code"], json!(PARSE_ERROR));
+        assert_eq!(responses[1]["id"], json!(1));
+    }
+}
@@ -1,29 +1,318 @@
-#[macro_use]
-extern crate lazy_static;
-
-mod lox;
-mod scanner;
-mod parser;
-mod environment;
-mod interpreter;
-mod lox_object;
-mod native_functions;
+extern crate rlox;
 
 use std::env;
-use lox::Lox;
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::process;
+
+use rlox::{DebugAdapter, Debugger, LanguageServer, Lox, LoxError, Profiler, Tracer, LINTS};
+
+#[derive(Default)]
+struct Options {
+    debug: bool,
+    dump_ast: bool,
+    dump_tokens: bool,
+    gc_stats: bool,
+    gc_stress: bool,
+    include_trivia: bool,
+    optimize: bool,
+    profile: bool,
+    profile_stacks: Option<String>,
+    trace: bool,
+    trace_expressions: bool,
+    script: Option<String>,
+}
+
+impl Options {
+    fn parse(args: &[String]) -> Option<Self> {
+        let mut options = Options::default();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--debug" => options.debug = true,
+                "--dump-ast" => options.dump_ast = true,
+                "--dump-tokens" => options.dump_tokens = true,
+                "--gc-stats" => options.gc_stats = true,
+                "--gc-stress" => options.gc_stress = true,
+                "--trivia" => options.include_trivia = true,
+                "-O" => options.optimize = true,
+                "--profile" => options.profile = true,
+                "--profile-stacks" => {
+                    options.profile = true;
+                    options.profile_stacks = Some(args.next()?.to_owned());
+                }
+                "--trace" => options.trace = true,
+                "--trace-expressions" => {
+                    options.trace = true;
+                    options.trace_expressions = true;
+                }
+                _ if arg.starts_with('-') => return None,
+                _ if options.script.is_none() => options.script = Some(arg.to_owned()),
+                _ => return None,
+            }
+        }
+        // The debugger and tracer need the whole program up front, and only one
+        // of them or the profiler can watch it at a time.
+        let watchers = [options.debug, options.trace, options.profile].iter().filter(|&&on| on).count();
+        if ((options.debug || options.trace) && options.script.is_none()) || watchers > 1 {
+            return None;
+        }
+        Some(options)
+    }
+}
 
 fn main() {
     let args: Vec<String> = env::args().collect();
-    let ref program_name = args[0];
-    if args.len() > 2 {
-        println!("Usage: {} [script]", program_name);
-        return;
+    let program_name = &args[0];
+    match args.get(1).map(String::as_str) {
+        Some("fmt") => process::exit(run_fmt(program_name, &args[2..])),
+        Some("lint") => process::exit(run_lint(program_name, &args[2..])),
+        Some("lsp") => process::exit(run_lsp()),
+        Some("dap") => process::exit(run_dap()),
+        _ => {}
     }
+    let options = match Options::parse(&args[1..]) {
+        Some(options) => options,
+        None => {
+            println!("Usage: {} [-O] [--dump-ast] [--dump-tokens [--trivia]] [script]", program_name);
+            println!("       {} --debug script", program_name);
+            println!("       {} --trace [--trace-expressions] script", program_name);
+            println!("       {} --profile [--profile-stacks <file>] [script]", program_name);
+            println!("       {} [--gc-stress] [--gc-stats] [script]", program_name);
+            println!("       {} fmt [--check] [file...]", program_name);
+            println!("       {} lint [--allow <lint>]... file...", program_name);
+            println!("       {} lsp", program_name);
+            println!("       {} dap", program_name);
+            return;
+        }
+    };
     let mut lox = Lox::new();
+    lox.set_gc_stress(options.gc_stress);
+    lox.set_optimize(options.optimize);
+
+    let profiler = if options.profile {
+        let profiler = Profiler::new();
+        lox.set_hook(profiler.clone());
+        Some(profiler)
+    } else {
+        None
+    };
+
+    let exit_code = match options.script {
+        Some(ref file_name) => run_file(&mut lox, &options, file_name),
+        None => {
+            run_prompt(&mut lox, &options);
+            0
+        }
+    };
+    if let Some(profiler) = profiler {
+        eprint!("{}", profiler.report());
+        if let Some(ref file_name) = options.profile_stacks {
+            if let Err(err) = fs::write(file_name, profiler.collapsed_stacks()) {
+                report(&mut lox, &LoxError::from(err));
+            }
+        }
+    }
+    if options.gc_stats {
+        eprintln!("{}", lox.gc_stats());
+    }
+    process::exit(exit_code);
+}
+
+fn run_file(lox: &mut Lox, options: &Options, file_name: &str) -> i32 {
+    let result = fs::read_to_string(file_name)
+        .map_err(LoxError::from)
+        .and_then(|source| {
+            if options.debug {
+                lox.set_hook(Debugger::new(&source, io::BufReader::new(io::stdin()), io::stdout()));
+            } else if options.trace {
+                lox.set_hook(Tracer::new(&source, io::stderr(), options.trace_expressions));
+            }
+            run(lox, options, &source)
+        });
+    match result {
+        Ok(()) => 0,
+        Err(err) => {
+            report(lox, &err);
+            err.exit_code()
+        }
+    }
+}
 
-    if args.len() == 2 {
-        lox.run_file(&args[1]);
+fn run_prompt(lox: &mut Lox, options: &Options) {
+    loop {
+        let stdout = io::stdout();
+        let mut stdout = stdout.lock();
+        if write!(stdout, "> ").and_then(|_| stdout.flush()).is_err() {
+            break;
+        }
+        let mut input = String::new();
+        match io::stdin().read_line(&mut input) {
+            Ok(0) | Err(_) => break,
+            Ok(_) => {}
+        }
+        drop(stdout);
+        if let Err(err) = run(lox, options, &input) {
+            report(lox, &err);
+        }
+    }
+}
+
+fn run(lox: &mut Lox, options: &Options, source: &str) -> Result<(), LoxError> {
+    if options.dump_tokens {
+        let (tokens, scanned) = lox.dump_tokens(source, options.include_trivia);
+        writeln!(io::stdout().lock(), "{}", tokens)?;
+        scanned
+    } else if options.dump_ast {
+        let ast = lox.dump_ast(source)?;
+        writeln!(io::stdout().lock(), "{}", ast)?;
+        Ok(())
     } else {
-        lox.run_prompt();
+        lox.run(source)
+    }
+}
+
+/// Reports an error on stderr. If even that fails, there's nowhere left to say so.
+fn report(lox: &mut Lox, err: &LoxError) {
+    let _ = lox.report(err);
+}
+
+/// Formats the given files in place, or stdin to stdout when there are none. With
+/// `--check` nothing is written and the exit code is 1 if any file would change.
+fn run_fmt(program_name: &str, args: &[String]) -> i32 {
+    let check = args.iter().any(|arg| arg == "--check");
+    let files: Vec<&String> = args.iter().filter(|arg| *arg != "--check").collect();
+    if let Some(flag) = files.iter().find(|arg| arg.starts_with('-')) {
+        println!("Unknown option '{}'.\nUsage: {} fmt [--check] [file...]", flag, program_name);
+        return 64;
+    }
+    let mut lox = Lox::new();
+
+    if files.is_empty() {
+        let mut source = String::new();
+        let result = io::stdin()
+            .read_to_string(&mut source)
+            .map_err(LoxError::from)
+            .and_then(|_| lox.format(&source));
+        return match result {
+            Ok(ref formatted) if check => (formatted != &source) as i32,
+            Ok(formatted) => match write!(io::stdout().lock(), "{}", formatted) {
+                Ok(()) => 0,
+                Err(err) => {
+                    report(&mut lox, &LoxError::from(err));
+                    74
+                }
+            },
+            Err(err) => {
+                report(&mut lox, &err);
+                err.exit_code()
+            }
+        };
+    }
+
+    let mut exit_code = 0;
+    for file_name in files {
+        let result = fs::read_to_string(file_name)
+            .map_err(LoxError::from)
+            .and_then(|source| lox.format(&source).map(|formatted| (source, formatted)));
+        match result {
+            Ok((ref source, ref formatted)) if source == formatted => {}
+            Ok(_) if check => {
+                if let Err(err) = writeln!(io::stdout().lock(), "Would reformat {}", file_name) {
+                    report(&mut lox, &LoxError::from(err));
+                    return 74;
+                }
+                exit_code = 1;
+            }
+            Ok((_, formatted)) => {
+                if let Err(err) = fs::write(file_name, formatted) {
+                    report(&mut lox, &LoxError::from(err));
+                    exit_code = 74;
+                }
+            }
+            Err(err) => {
+                eprint!("{}: ", file_name);
+                report(&mut lox, &err);
+                exit_code = err.exit_code();
+            }
+        }
+    }
+    exit_code
+}
+
+/// Lints the given files, printing each warning. The exit code is 1 if any
+/// warnings were reported.
+fn run_lint(program_name: &str, args: &[String]) -> i32 {
+    let mut disabled = Vec::new();
+    let mut files = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--allow" => match args.next() {
+                Some(name) if LINTS.contains(&name.as_str()) => disabled.push(name.to_owned()),
+                _ => {
+                    println!("Expected one of {} after '--allow'.", LINTS.join(", "));
+                    return 64;
+                }
+            },
+            _ if arg.starts_with('-') => {
+                println!("Unknown option '{}'.\nUsage: {} lint [--allow <lint>]... file...", arg, program_name);
+                return 64;
+            }
+            _ => files.push(arg),
+        }
+    }
+    let lox = Lox::new();
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+
+    let mut exit_code = 0;
+    for file_name in files {
+        let result = fs::read_to_string(file_name)
+            .map_err(LoxError::from)
+            .and_then(|source| lox.lint(&source, &disabled));
+        match result {
+            Ok(lints) => {
+                for lint in lints.iter() {
+                    if let Err(err) = writeln!(stdout, "{}: {}", file_name, lint) {
+                        eprintln!("{}", err);
+                        return 74;
+                    }
+                    exit_code = 1;
+                }
+            }
+            Err(err) => {
+                eprintln!("{}: {}", file_name, err);
+                exit_code = err.exit_code();
+            }
+        }
+    }
+    exit_code
+}
+
+/// Serves the Language Server Protocol over stdin and stdout.
+fn run_lsp() -> i32 {
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    let mut server = LanguageServer::new(stdin.lock(), stdout.lock());
+    match server.run() {
+        Ok(exit_code) => exit_code,
+        Err(err) => {
+            eprintln!("{}", err);
+            1
+        }
+    }
+}
+
+/// Serves the Debug Adapter Protocol over stdin and stdout.
+fn run_dap() -> i32 {
+    let mut adapter = DebugAdapter::new(io::stdin().lock(), io::stdout());
+    match adapter.run() {
+        Ok(exit_code) => exit_code,
+        Err(err) => {
+            eprintln!("{}", err);
+            1
+        }
     }
 }
@@ -1,20 +1,251 @@
+use std::fmt;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
-use interpreter::{Interpreter, RuntimeResult};
+
+use interpreter::{Interpreter, RuntimeError, RuntimeResult};
 use lox_object::{Callable, LoxObject};
-use scanner::Literal;
+use scanner::Token;
+
+/// The number of arguments a callable accepts.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Arity {
+    Fixed(usize),
+    Variadic,
+}
+
+impl Arity {
+    pub fn accepts(&self, count: usize) -> bool {
+        match *self {
+            Arity::Fixed(n) => n == count,
+            Arity::Variadic => true,
+        }
+    }
+}
+
+impl fmt::Display for Arity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Arity::Fixed(n) => write!(f, "{}", n),
+            Arity::Variadic => write!(f, "any number of"),
+        }
+    }
+}
+
+type NativeFn = dyn Fn(&NativeArgs) -> RuntimeResult<LoxObject>;
+
+/// A callable backed by a Rust closure, registered through `Interpreter::define_native`.
+pub struct NativeFunction {
+    name: String,
+    arity: Arity,
+    function: Box<NativeFn>,
+}
+
+impl NativeFunction {
+    pub fn new<F, R>(name: &str, arity: Arity, function: F) -> Self
+    where
+        F: Fn(&NativeArgs) -> RuntimeResult<R> + 'static,
+        R: Into<LoxObject>,
+    {
+        NativeFunction {
+            name: name.to_owned(),
+            arity,
+            function: Box::new(move |args| function(args).map(Into::into)),
+        }
+    }
+}
+
+impl Callable for NativeFunction {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn arity(&self) -> Arity {
+        self.arity
+    }
 
-pub struct Clock;
+    fn call(&self, _interpreter: &mut Interpreter, paren: &Token, arguments: &[LoxObject]) -> RuntimeResult<LoxObject> {
+        let args = NativeArgs {
+            name: &self.name,
+            paren,
+            values: arguments,
+        };
+        (self.function)(&args)
+    }
+}
+
+/// The arguments passed to a native function, with helpers that convert them to
+/// Rust values and report type mismatches as runtime errors at the call site.
+pub struct NativeArgs<'a> {
+    name: &'a str,
+    paren: &'a Token,
+    values: &'a [LoxObject],
+}
+
+impl<'a> NativeArgs<'a> {
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
 
-impl Callable for Clock {
-    fn arity(&self) -> usize {
-        0
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
     }
 
-    fn call(&self, interpreter: &mut Interpreter, arguments: &[LoxObject]) -> RuntimeResult<LoxObject> {
-        let dur: Duration = SystemTime::now().duration_since(UNIX_EPOCH).expect("time went backwards");
+    pub fn values(&self) -> &[LoxObject] {
+        self.values
+    }
 
-        let ms: f64 = dur.as_secs() as f64 * 1e3 + dur.subsec_nanos() as f64 / 1e6;
+    pub fn get<T: FromLoxObject>(&self, index: usize) -> RuntimeResult<T> {
+        let value = match self.values.get(index) {
+            Some(value) => value,
+            None => return Err(self.error(&format!("Missing argument {} to '{}'.", index + 1, self.name))),
+        };
+        T::from_lox_object(value).ok_or_else(|| {
+            self.error(&format!(
+                "Expected argument {} to '{}' to be {}.",
+                index + 1,
+                self.name,
+                T::TYPE_NAME
+            ))
+        })
+    }
+
+    /// A number argument, with an integer converted to a float.
+    pub fn number(&self, index: usize) -> RuntimeResult<f64> {
+        self.get(index)
+    }
+
+    pub fn integer(&self, index: usize) -> RuntimeResult<i64> {
+        self.get(index)
+    }
+
+    pub fn string(&self, index: usize) -> RuntimeResult<String> {
+        self.get(index)
+    }
+
+    pub fn boolean(&self, index: usize) -> RuntimeResult<bool> {
+        self.get(index)
+    }
+
+    /// Creates a runtime error located at the call site.
+    pub fn error(&self, message: &str) -> RuntimeError {
+        RuntimeError::new(self.paren, message)
+    }
+}
+
+/// Conversion from a Lox value into a Rust value, used for native function arguments.
+pub trait FromLoxObject: Sized {
+    const TYPE_NAME: &'static str;
+
+    fn from_lox_object(value: &LoxObject) -> Option<Self>;
+}
+
+impl FromLoxObject for f64 {
+    const TYPE_NAME: &'static str = "a number";
+
+    fn from_lox_object(value: &LoxObject) -> Option<Self> {
+        value.as_number()
+    }
+}
+
+impl FromLoxObject for i64 {
+    const TYPE_NAME: &'static str = "an integer";
+
+    fn from_lox_object(value: &LoxObject) -> Option<Self> {
+        value.as_integer()
+    }
+}
+
+impl FromLoxObject for bool {
+    const TYPE_NAME: &'static str = "a boolean";
+
+    fn from_lox_object(value: &LoxObject) -> Option<Self> {
+        value.as_bool()
+    }
+}
+
+impl FromLoxObject for String {
+    const TYPE_NAME: &'static str = "a string";
+
+    fn from_lox_object(value: &LoxObject) -> Option<Self> {
+        value.as_str().map(str::to_owned)
+    }
+}
+
+impl FromLoxObject for LoxObject {
+    const TYPE_NAME: &'static str = "a value";
+
+    fn from_lox_object(value: &LoxObject) -> Option<Self> {
+        Some(value.clone())
+    }
+}
+
+/// The names of the natives every interpreter starts with, for tools that
+/// need to know them without building an interpreter.
+pub const BUILTIN_NAMES: &[&str] = &["clock"];
+
+/// Registers the natives every interpreter starts with.
+pub fn define_builtins(interpreter: &mut Interpreter) {
+    interpreter.define_native("clock", Arity::Fixed(0), |_| Ok(clock()));
+}
+
+fn clock() -> f64 {
+    let dur: Duration = SystemTime::now().duration_since(UNIX_EPOCH).expect("time went backwards");
+    dur.as_secs() as f64 * 1e3 + dur.subsec_nanos() as f64 / 1e6
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use lox::{Lox, LoxError};
+
+    #[test]
+    fn test_native_with_converted_arguments() {
+        let mut lox = Lox::new();
+        lox.define_native("hypot", Arity::Fixed(2), |args| Ok(args.number(0)?.hypot(args.number(1)?)));
+        lox.define_native("shout", Arity::Fixed(1), |args| Ok(args.string(0)?.to_uppercase()));
+        lox.run("var h = hypot(3, 4); var s = shout(\"hi\");").expect("should run");
+        assert_eq!(lox.get_global("h"), Some(LoxObject::from(5.0)));
+        assert_eq!(lox.get_global("s"), Some(LoxObject::from("HI")));
+    }
+
+    #[test]
+    fn test_variadic_native() {
+        let mut lox = Lox::new();
+        lox.define_native("sum", Arity::Variadic, |args| {
+            let mut total = 0.0;
+            for i in 0..args.len() {
+                total += args.number(i)?;
+            }
+            Ok(total)
+        });
+        lox.run("var none = sum(); var some = sum(1, 2, 3);").expect("should run");
+        assert_eq!(lox.get_global("none"), Some(LoxObject::from(0.0)));
+        assert_eq!(lox.get_global("some"), Some(LoxObject::from(6.0)));
+    }
+
+    #[test]
+    fn test_native_type_mismatch_is_runtime_error() {
+        let mut lox = Lox::new();
+        lox.define_native("negate", Arity::Fixed(1), |args| Ok(!args.boolean(0)?));
+        match lox.run("negate(1);") {
+            Err(LoxError::Runtime(err)) => {
+                assert_eq!(err.message, "Expected argument 1 to 'negate' to be a boolean.");
+                assert_eq!(err.token.line, 1);
+            }
+            other => panic!("expected a runtime error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_builtin_names_match_the_builtins() {
+        assert_eq!(Interpreter::new().global_names(), BUILTIN_NAMES);
+    }
 
-        Ok(Literal::Number(ms).to_lox_object())
+    #[test]
+    fn test_native_arity_mismatch() {
+        let mut lox = Lox::new();
+        match lox.run("clock(1);") {
+            Err(LoxError::Runtime(err)) => assert_eq!(err.message, "Expected 0 arguments but got 1."),
+            other => panic!("expected a runtime error, got {:?}", other),
+        }
     }
 }
@@ -0,0 +1,236 @@
+use std::mem;
+
+use interpreter::{binary, unary};
+use limits::MAX_NESTING;
+use lox_object::LoxObject;
+use parser::ast::{Expr, Stmt, AST};
+use parser::chain;
+use scanner::TokenType;
+
+/// Rewrites a syntax tree before it runs: operators whose operands are all
+/// literals are folded into their result, and branches and loops whose
+/// condition is a literal lose the code that can never run. An operation
+/// that would fail at runtime, such as dividing by zero, is left in place so
+/// it still reports its error when it's reached. So is anything nested too
+/// deeply for the interpreter to run, so that it still overflows. A folded
+/// string concatenation still counts against the memory limit, since the
+/// interpreter checks a string literal fits whenever it evaluates one.
+pub struct Optimizer {
+    depth: usize,
+}
+
+impl Optimizer {
+    pub fn new() -> Self {
+        Optimizer { depth: 0 }
+    }
+
+    pub fn optimize(&mut self, ast: AST) -> AST {
+        AST {
+            root: ast.root.into_iter().filter_map(|stmt| self.stmt(*stmt)).collect(),
+        }
+    }
+
+    /// The optimized statement, or `None` if it does nothing.
+    fn stmt(&mut self, stmt: Stmt) -> Option<Box<Stmt>> {
+        if self.depth >= MAX_NESTING {
+            return Some(Box::new(stmt));
+        }
+        self.depth += 1;
+        let stmt = self.optimize_stmt(stmt);
+        self.depth -= 1;
+        stmt
+    }
+
+    fn expr(&mut self, expr: &mut Box<Expr>) {
+        if self.depth >= MAX_NESTING {
+            return;
+        }
+        self.depth += 1;
+        self.optimize_expr(expr);
+        self.depth -= 1;
+    }
+
+    fn optimize_stmt(&mut self, stmt: Stmt) -> Option<Box<Stmt>> {
+        let stmt = match stmt {
+            Stmt::Block(statements) => Stmt::Block(statements.into_iter().filter_map(|stmt| self.stmt(*stmt)).collect()),
+            Stmt::Expression(mut expr) => {
+                self.expr(&mut expr);
+                Stmt::Expression(expr)
+            }
+            Stmt::If(keyword, mut condition, then_clause, maybe_else_clause) => {
+                self.expr(&mut condition);
+                let then_clause = self.stmt(*then_clause);
+                let else_clause = maybe_else_clause.and_then(|else_clause| self.stmt(*else_clause));
+                match truthiness(&condition) {
+                    Some(true) => return then_clause,
+                    Some(false) => return else_clause,
+                    None => Stmt::If(keyword, condition, then_clause.unwrap_or_else(empty), else_clause),
+                }
+            }
+            Stmt::Print(keyword, mut expr) => {
+                self.expr(&mut expr);
+                Stmt::Print(keyword, expr)
+            }
+            Stmt::Var(name, mut initializer, binding) => {
+                self.expr(&mut initializer);
+                Stmt::Var(name, initializer, binding)
+            }
+            Stmt::While(keyword, mut condition, body) => {
+                self.expr(&mut condition);
+                if truthiness(&condition) == Some(false) {
+                    return None;
+                }
+                Stmt::While(keyword, condition, self.stmt(*body).unwrap_or_else(empty))
+            }
+        };
+        Some(Box::new(stmt))
+    }
+
+    fn optimize_expr(&mut self, expr: &mut Box<Expr>) {
+        let optimized = match **expr {
+            Expr::Assign(_, ref mut value, _) => {
+                self.expr(value);
+                None
+            }
+            Expr::Literal(_) | Expr::Variable(..) => None,
+            Expr::Logical(..) | Expr::Binary(..) => Some(self.optimize_chain(mem::replace(expr, chain::placeholder()))),
+            Expr::Call(ref mut callee, _, ref mut arguments) => {
+                self.expr(callee);
+                for argument in arguments {
+                    self.expr(argument);
+                }
+                None
+            }
+            Expr::Unary(ref token, ref mut e) => {
+                self.expr(e);
+                match **e {
+                    Expr::Literal(ref literal) => match unary(token, &literal.clone().to_lox_object()) {
+                        Ok(LoxObject::Literal(value)) => Some(Box::new(Expr::Literal(value))),
+                        _ => None,
+                    },
+                    _ => None,
+                }
+            }
+            Expr::Grouping(ref mut e) => {
+                self.expr(e);
+                match **e {
+                    Expr::Literal(_) => Some(mem::replace(e, chain::placeholder())),
+                    _ => None,
+                }
+            }
+        };
+        if let Some(optimized) = optimized {
+            *expr = optimized;
+        }
+    }
+
+    /// Takes a chain of binary and logical operators apart, then puts it back
+    /// together from the innermost link out, folding links as it goes, so that
+    /// a long chain doesn't recurse.
+    fn optimize_chain(&mut self, expr: Box<Expr>) -> Box<Expr> {
+        let mut links = Vec::new();
+        let mut operand = expr;
+        while let Some(left) = chain::take_left(&mut operand) {
+            links.push(operand);
+            operand = left;
+        }
+        self.expr(&mut operand);
+        for link in links.into_iter().rev() {
+            operand = self.optimize_link(link, operand);
+        }
+        operand
+    }
+
+    /// Puts `left` back as the left operand of a link, or folds the two.
+    fn optimize_link(&mut self, mut link: Box<Expr>, left: Box<Expr>) -> Box<Expr> {
+        match *link {
+            Expr::Logical(ref mut slot, ref token, ref mut right) => {
+                self.expr(right);
+                match truthiness(&left) {
+                    // The left operand decides the result on its own.
+                    Some(truthy) if truthy == (token.token_type == TokenType::OR) => return left,
+                    Some(_) => return mem::replace(right, chain::placeholder()),
+                    None => *slot = left,
+                }
+            }
+            Expr::Binary(ref mut slot, ref token, ref mut right) => {
+                self.expr(right);
+                if let (Expr::Literal(ref l), Expr::Literal(ref r)) = (&*left, &**right) {
+                    let left = l.clone().to_lox_object();
+                    let right = r.clone().to_lox_object();
+                    if let Ok(LoxObject::Literal(value)) = binary(&left, token, &right) {
+                        return Box::new(Expr::Literal(value));
+                    }
+                }
+                *slot = left;
+            }
+            _ => unreachable!("only binary and logical expressions are links"),
+        }
+        link
+    }
+}
+
+impl Default for Optimizer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Whether an expression is a literal that is always truthy or always falsy.
+fn truthiness(expr: &Expr) -> Option<bool> {
+    match *expr {
+        Expr::Literal(ref literal) => Some(literal.clone().to_lox_object().is_truthy()),
+        _ => None,
+    }
+}
+
+/// A statement that does nothing, for a branch or loop body that was removed.
+fn empty() -> Box<Stmt> {
+    Box::new(Stmt::Block(Vec::new()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use parser::{self, ast_printer::AstPrinter};
+
+    fn optimize(source: &str) -> String {
+        AstPrinter::new().print(&Optimizer::new().optimize(parser::parse(source)))
+    }
+
+    #[test]
+    fn test_folds_constant_expressions() {
+        assert_eq!(optimize("print 1 - (2 * 3) < 4 == false;"), optimize("print false;"));
+        assert_eq!(optimize("print !nil;"), optimize("print true;"));
+        assert_eq!(optimize("print -(1 + 2);"), optimize("print -3;"));
+        assert_eq!(optimize("print 7 ~/ 2 + 7 % 2 * 1.5;"), optimize("print 4.5;"));
+        assert_eq!(optimize("print false or x;"), optimize("print x;"));
+        assert_eq!(optimize("print 1 and x;"), optimize("print x;"));
+        assert_eq!(optimize("print nil and x;"), optimize("print nil;"));
+    }
+
+    #[test]
+    fn test_leaves_runtime_errors_in_place() {
+        assert_eq!(optimize("print 1 / (1 - 1);"), "(print (/ 1 0))");
+        assert_eq!(optimize("print -\"a\";"), "(print (- \"a\"))");
+        assert_eq!(optimize("print 9223372036854775807 + 1;"), "(print (+ 9223372036854775807 1))");
+        assert_eq!(optimize("print (\"a\" + 1) + 2;"), "(print (+ (group (+ \"a\" 1)) 2))");
+    }
+
+    #[test]
+    fn test_folds_string_concatenation() {
+        assert_eq!(optimize("print \"a\" + \"b\";"), optimize("print \"ab\";"));
+        assert_eq!(optimize("print (\"a\" + \"b\") == \"ab\";"), optimize("print true;"));
+        assert_eq!(optimize("print \"a\" + \"b\" + \"c\";"), optimize("print \"abc\";"));
+    }
+
+    #[test]
+    fn test_removes_dead_branches() {
+        assert_eq!(optimize("if (false) print 1; print 2;"), optimize("print 2;"));
+        assert_eq!(optimize("if (1 > 2) print 1; else print 2;"), optimize("print 2;"));
+        assert_eq!(optimize("if (\"yes\") { print 1; } else print 2;"), optimize("{ print 1; }"));
+        assert_eq!(optimize("while (nil) print 1;"), "");
+        assert_eq!(optimize("while (x) if (false) print 1;"), optimize("while (x) {}"));
+        assert_eq!(optimize("if (x) if (false) print 1;"), optimize("if (x) {}"));
+    }
+}
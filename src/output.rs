@@ -0,0 +1,81 @@
+use std::cell::RefCell;
+use std::io;
+use std::io::prelude::*;
+use std::rc::Rc;
+
+/// An in-memory sink whose clones share the same buffer, so a host can hand one
+/// clone to the interpreter and read what was written through another.
+#[derive(Clone, Default)]
+pub struct OutputBuffer {
+    buffer: Rc<RefCell<Vec<u8>>>,
+}
+
+impl OutputBuffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn contents(&self) -> String {
+        String::from_utf8_lossy(&self.buffer.borrow()).into_owned()
+    }
+
+    pub fn clear(&self) {
+        self.buffer.borrow_mut().clear();
+    }
+}
+
+impl Write for OutputBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use lox::{Lox, LoxError};
+
+    #[test]
+    fn test_capture_program_and_error_output() {
+        let output = OutputBuffer::new();
+        let errors = OutputBuffer::new();
+        let mut lox = Lox::new();
+        lox.set_output(output.clone());
+        lox.set_error_output(errors.clone());
+
+        lox.run("print 1 + 2; print \"two\";").expect("should run");
+        assert_eq!(output.contents(), "3\ntwo\n");
+        assert_eq!(errors.contents(), "");
+
+        let err = lox.run("print -\"x\";").expect_err("should fail");
+        lox.report(&err).expect("should report");
+        assert_eq!(output.contents(), "3\ntwo\n");
+        assert_eq!(errors.contents(), "Operand must be a number.\n[line 1]\n");
+    }
+
+    struct ClosedOutput;
+
+    impl Write for ClosedOutput {
+        fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+            Err(io::Error::new(io::ErrorKind::BrokenPipe, "closed"))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_failed_output_is_an_io_error() {
+        let mut lox = Lox::new();
+        lox.set_output(ClosedOutput);
+        match lox.run("print 1;") {
+            Err(LoxError::Io(ref err)) => assert_eq!(err.kind(), io::ErrorKind::BrokenPipe),
+            other => panic!("expected an I/O error, got {:?}", other),
+        }
+    }
+}
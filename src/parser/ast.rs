@@ -1,3 +1,6 @@
+use std::cell::Cell;
+
+use resolver::Binding;
 use scanner::{Literal, Token};
 
 pub struct AST {
@@ -5,29 +8,29 @@ pub struct AST {
 }
 
 pub enum Expr {
-    Assign(Token, Box<Expr>),
+    Assign(Token, Box<Expr>, Cell<Binding>),
     Literal(Literal),
     Logical(Box<Expr>, Token, Box<Expr>),
     Binary(Box<Expr>, Token, Box<Expr>),
     Call(Box<Expr>, Token, Vec<Box<Expr>>),
     Unary(Token, Box<Expr>),
     Grouping(Box<Expr>),
-    Variable(Token),
+    Variable(Token, Cell<Binding>),
 }
 
 pub trait ExprVisitor<E> {
-    fn visit_expr(&mut self, expr: &Box<Expr>) -> E;
+    fn visit_expr(&mut self, expr: &Expr) -> E;
 }
 
 pub enum Stmt {
     Block(Vec<Box<Stmt>>),
     Expression(Box<Expr>),
-    If(Box<Expr>, Box<Stmt>, Option<Box<Stmt>>),
-    Print(Box<Expr>),
-    Var(Token, Box<Expr>),
-    While(Box<Expr>, Box<Stmt>),
+    If(Token, Box<Expr>, Box<Stmt>, Option<Box<Stmt>>),
+    Print(Token, Box<Expr>),
+    Var(Token, Box<Expr>, Cell<Binding>),
+    While(Token, Box<Expr>, Box<Stmt>),
 }
 
 pub trait StmtVisitor<E> {
-    fn visit_stmt(&mut self, stmt: &Box<Stmt>) -> E;
+    fn visit_stmt(&mut self, stmt: &Stmt) -> E;
 }
@@ -1,59 +1,149 @@
-// use parser::ast::{Expr, ExprVisitor};
-
-// pub struct AstPrinter;
-
-// impl AstPrinter {
-//     pub fn new() -> Self {
-//         AstPrinter {}
-//     }
-
-//     pub fn print(&mut self, ast: &AST) -> String {
-//         self.visit_expr(&ast.root)
-//     }
-
-//     fn parenthesize(&mut self, name: &str, exprs: &[&Box<Expr>]) -> String {
-//         let mut expr_str = String::new();
-//         for expr in exprs {
-//             expr_str += " ";
-//             expr_str += &self.visit_expr(expr);
-//         }
-//         format!("({}{})", name, expr_str)
-//     }
-// }
-
-// impl ExprVisitor<String> for AstPrinter {
-//     fn visit_expr(&mut self, expr: &Box<Expr>) -> String {
-//         match **expr {
-//             Expr::Literal(ref literal) => literal.to_string(),
-//             Expr::Binary(ref lhs, ref token, ref rhs) => {
-//                 self.parenthesize(&token.lexeme, &[lhs, rhs])
-//             }
-//             Expr::Unary(ref token, ref e) => self.parenthesize(&token.lexeme, &[e]),
-//             Expr::Grouping(ref e) => self.parenthesize("group", &[e]),
-//         }
-//     }
-// }
-
-// #[cfg(test)]
-// mod test {
-//     use super::*;
-//     use scanner::{Literal, Token, TokenType};
-
-//     #[test]
-//     fn test_printer() {
-//         let expr = Box::new(Expr::Binary(
-//             Box::new(Expr::Unary(
-//                 Token::new(TokenType::MINUS, "-", Literal::Nil, 1),
-//                 Box::new(Expr::Literal(Literal::Number(123.0))),
-//             )),
-//             Token::new(TokenType::STAR, "*", Literal::Nil, 1),
-//             Box::new(Expr::Grouping(
-//                 Box::new(Expr::Literal(Literal::Number(45.67))),
-//             )),
-//         ));
-//         // let expr = Expr::Literal(Literal::Number(123.0));
-
-//         let mut printer = AstPrinter {};
-//         println!("{}", printer.print_expr(&expr));
-//     }
-// }
+use parser::ast::{Expr, ExprVisitor, Stmt, StmtVisitor, AST};
+use parser::chain;
+use scanner::Literal;
+
+/// Renders an AST as S-expressions, one statement per line with nested
+/// statements indented beneath their parent.
+pub struct AstPrinter {
+    indent: usize,
+}
+
+impl AstPrinter {
+    pub fn new() -> Self {
+        AstPrinter { indent: 0 }
+    }
+
+    pub fn print(&mut self, ast: &AST) -> String {
+        let statements: Vec<String> = ast.root.iter().map(|stmt| self.visit_stmt(stmt)).collect();
+        statements.join("\n")
+    }
+
+    fn parenthesize(&mut self, name: &str, exprs: &[&Expr]) -> String {
+        let mut expr_str = String::new();
+        for expr in exprs {
+            expr_str += " ";
+            expr_str += &self.visit_expr(expr);
+        }
+        format!("({}{})", name, expr_str)
+    }
+
+    /// Prints `head` followed by each statement on its own, further indented line.
+    fn nest(&mut self, head: &str, stmts: &[&Stmt]) -> String {
+        self.indent += 1;
+        let mut result = format!("({}", head);
+        for stmt in stmts {
+            result += "\n";
+            result += &"  ".repeat(self.indent);
+            result += &self.visit_stmt(stmt);
+        }
+        self.indent -= 1;
+        result + ")"
+    }
+}
+
+impl Default for AstPrinter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StmtVisitor<String> for AstPrinter {
+    fn visit_stmt(&mut self, stmt: &Stmt) -> String {
+        match *stmt {
+            Stmt::Block(ref statements) => {
+                let statements: Vec<&Stmt> = statements.iter().map(|stmt| &**stmt).collect();
+                self.nest("block", &statements)
+            }
+            Stmt::Expression(ref expr) => self.parenthesize(";", &[&**expr]),
+            Stmt::If(_, ref condition, ref then_branch, ref maybe_else_branch) => {
+                let head = format!("if {}", self.visit_expr(condition));
+                match *maybe_else_branch {
+                    Some(ref else_branch) => self.nest(&head, &[&**then_branch, &**else_branch]),
+                    None => self.nest(&head, &[&**then_branch]),
+                }
+            }
+            Stmt::Print(_, ref expr) => self.parenthesize("print", &[&**expr]),
+            Stmt::Var(ref name, ref initializer, _) => self.parenthesize(&format!("var {}", name.lexeme), &[&**initializer]),
+            Stmt::While(_, ref condition, ref body) => {
+                let head = format!("while {}", self.visit_expr(condition));
+                self.nest(&head, &[&**body])
+            }
+        }
+    }
+}
+
+impl ExprVisitor<String> for AstPrinter {
+    fn visit_expr(&mut self, expr: &Expr) -> String {
+        match *expr {
+            Expr::Assign(ref name, ref value, _) => self.parenthesize(&format!("= {}", name.lexeme), &[&**value]),
+            Expr::Literal(ref literal) => match *literal {
+                Literal::String(ref s) => format!("{:?}", s),
+                _ => literal.to_string(),
+            },
+            Expr::Logical(..) | Expr::Binary(..) => {
+                let (operand, links) = chain::split(expr);
+                let mut result: String = links.iter().rev().map(|link| format!("({} ", link.operator.lexeme)).collect();
+                result += &self.visit_expr(operand);
+                for link in links {
+                    result += " ";
+                    result += &self.visit_expr(link.right);
+                    result += ")";
+                }
+                result
+            }
+            Expr::Call(ref callee, _, ref arguments) => {
+                let mut exprs = vec![&**callee];
+                exprs.extend(arguments.iter().map(|argument| &**argument));
+                self.parenthesize("call", &exprs)
+            }
+            Expr::Unary(ref token, ref e) => self.parenthesize(&token.lexeme, &[&**e]),
+            Expr::Grouping(ref e) => self.parenthesize("group", &[&**e]),
+            Expr::Variable(ref name, _) => name.lexeme.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use parser;
+    use scanner::{Token, TokenType};
+
+    fn print_source(source: &str) -> String {
+        let ast = parser::parse(source);
+        AstPrinter::new().print(&ast)
+    }
+
+    #[test]
+    fn test_printer() {
+        let expr = Box::new(Expr::Binary(
+            Box::new(Expr::Unary(
+                Token::new(TokenType::MINUS, "-", Literal::Nil, 1, 1),
+                Box::new(Expr::Literal(Literal::Integer(123))),
+            )),
+            Token::new(TokenType::STAR, "*", Literal::Nil, 1, 1),
+            Box::new(Expr::Grouping(
+                Box::new(Expr::Literal(Literal::Number(45.67))),
+            )),
+        ));
+
+        let mut printer = AstPrinter::new();
+        assert_eq!(printer.visit_expr(&expr), "(* (- 123) (group 45.67))");
+    }
+
+    #[test]
+    fn test_print_statements() {
+        assert_eq!(
+            print_source("var a = \"hi\"; if (a and clock()) print a; else a = nil;"),
+            "(var a \"hi\")\n(if (and a (call clock))\n  (print a)\n  (; (= a nil)))"
+        );
+    }
+
+    #[test]
+    fn test_print_desugared_for_loop() {
+        assert_eq!(
+            print_source("for (var i = 0; i < 2; i = i + 1) print i;"),
+            "(block\n  (var i 0)\n  (while (< i 2)\n    (block\n      (print i)\n      (; (= i (+ i 1))))))"
+        );
+    }
+}
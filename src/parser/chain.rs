@@ -0,0 +1,108 @@
+//! Binary and logical operators associate to the left, so a run of them parses
+//! into a chain that nests one level deeper down its left operands for every
+//! operator. Nothing limits how long such a run is, so code that walks a tree
+//! goes along a chain in a loop rather than recursing into each left operand,
+//! and dropping a tree takes its chains apart the same way.
+
+use std::mem;
+
+use parser::ast::Expr;
+use scanner::{Literal, Token};
+
+/// One operator of a chain, with the operand to its right.
+pub struct Link<'a> {
+    /// The binary or logical expression this link is.
+    pub expr: &'a Expr,
+    pub operator: &'a Token,
+    pub right: &'a Expr,
+}
+
+/// Splits an expression into the operand at the bottom of its chain and the
+/// links above it, innermost first, which is the order they're evaluated in.
+/// An expression that isn't binary or logical has no links.
+pub fn split<'a>(expr: &'a Expr) -> (&'a Expr, Vec<Link<'a>>) {
+    let mut links = Vec::new();
+    let mut operand = expr;
+    while let Expr::Binary(ref left, ref operator, ref right) | Expr::Logical(ref left, ref operator, ref right) = *operand {
+        links.push(Link { expr: operand, operator, right });
+        operand = left;
+    }
+    links.reverse();
+    (operand, links)
+}
+
+/// How many links there are in an expression's chain.
+pub fn length(expr: &Expr) -> usize {
+    let mut length = 0;
+    let mut operand = expr;
+    while let Expr::Binary(ref left, ..) | Expr::Logical(ref left, ..) = *operand {
+        length += 1;
+        operand = left;
+    }
+    length
+}
+
+/// Takes the left operand out of a binary or logical expression, leaving a
+/// placeholder in its place.
+pub fn take_left(expr: &mut Expr) -> Option<Box<Expr>> {
+    match *expr {
+        Expr::Binary(ref mut left, ..) | Expr::Logical(ref mut left, ..) => Some(mem::replace(left, placeholder())),
+        _ => None,
+    }
+}
+
+/// An expression to leave behind where one was taken out of a tree.
+pub fn placeholder() -> Box<Expr> {
+    Box::new(Expr::Literal(Literal::Nil))
+}
+
+fn is_link(expr: &Expr) -> bool {
+    matches!(*expr, Expr::Binary(..) | Expr::Logical(..))
+}
+
+impl Drop for Expr {
+    fn drop(&mut self) {
+        let mut next = take_left_link(self);
+        while let Some(mut link) = next {
+            // The link is dropped once its own left link is out, so its drop
+            // doesn't recurse.
+            next = take_left_link(&mut link);
+        }
+    }
+}
+
+/// Takes the left operand out of a binary or logical expression if it's the
+/// next link of the chain.
+fn take_left_link(expr: &mut Expr) -> Option<Box<Expr>> {
+    match *expr {
+        Expr::Binary(ref left, ..) | Expr::Logical(ref left, ..) if is_link(left) => take_left(expr),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use scanner::TokenType;
+
+    fn chain(length: usize) -> Box<Expr> {
+        let plus = Token::new(TokenType::PLUS, "+", Literal::Nil, 1, 1);
+        let one = || Box::new(Expr::Literal(Literal::Integer(1)));
+        (0..length).fold(one(), |chain, _| Box::new(Expr::Binary(chain, plus.clone(), one())))
+    }
+
+    #[test]
+    fn test_splits_a_chain_innermost_first() {
+        let expr = chain(3);
+        let (operand, links) = split(&expr);
+        assert!(matches!(*operand, Expr::Literal(Literal::Integer(1))));
+        assert_eq!(links.len(), 3);
+        assert!(std::ptr::eq(links[2].expr, &*expr));
+        assert_eq!(split(operand).1.len(), 0);
+    }
+
+    #[test]
+    fn test_drops_long_chains_without_recursing() {
+        drop(chain(1_000_000));
+    }
+}
@@ -3,6 +3,8 @@ import sys
 
 def generate_AST(output_dir, enum_descriptions):
     with open(f'{output_dir}/ast.rs', 'w') as f:
+        f.write("use std::cell::Cell;\n\n")
+        f.write("use resolver::Binding;\n")
         f.write("use scanner::{Literal, Token};\n")
         write_ast_struct(f)
         for (base_name, types) in enum_descriptions:
@@ -30,7 +32,7 @@ def write_visitor(file, base_name):
     file.write('\n')
     file.write(f"pub trait {base_name.capitalize()}Visitor<E> {{\n")
     file.write(
-        f'    fn visit_{base_name}(&mut self, {base_name.lower()}: &Box<{base_name.capitalize()}>) -> E;\n')
+        f'    fn visit_{base_name}(&mut self, {base_name.lower()}: &{base_name.capitalize()}) -> E;\n')
     file.write('}\n')
 
 
@@ -46,21 +48,21 @@ if __name__ == '__main__':
 
     generate_AST(args.output_dir, [
         ('expr', [
-            ('Assign', ['Token', 'Box<Expr>']),
+            ('Assign', ['Token', 'Box<Expr>', 'Cell<Binding>']),
             ('Literal', ['Literal']),
             ('Logical', ['Box<Expr>', 'Token', 'Box<Expr>']),
             ('Binary', ['Box<Expr>', 'Token', 'Box<Expr>']),
             ('Call', ['Box<Expr>', 'Token', 'Vec<Box<Expr>>']),
             ('Unary', ['Token', 'Box<Expr>']),
             ('Grouping', ['Box<Expr>']),
-            ('Variable', ['Token'])
+            ('Variable', ['Token', 'Cell<Binding>'])
         ]),
         ('stmt', [
             ('Block', ['Vec<Box<Stmt>>']),
             ('Expression', ['Box<Expr>']),
-            ('If', ['Box<Expr>', 'Box<Stmt>', 'Option<Box<Stmt>>']),
-            ('Print', ['Box<Expr>']),
-            ('Var', ['Token', 'Box<Expr>']),
-            ('While', ['Box<Expr>', 'Box<Stmt>'])
+            ('If', ['Token', 'Box<Expr>', 'Box<Stmt>', 'Option<Box<Stmt>>']),
+            ('Print', ['Token', 'Box<Expr>']),
+            ('Var', ['Token', 'Box<Expr>', 'Cell<Binding>']),
+            ('While', ['Token', 'Box<Expr>', 'Box<Stmt>'])
         ])
     ])
@@ -0,0 +1,32 @@
+use parser::ast::{Expr, Stmt};
+
+/// The line of the first token in an expression, if it has any.
+pub fn expr_line(expr: &Expr) -> Option<i32> {
+    // The first token of a chain is in its innermost operand, or failing that,
+    // is its innermost operator.
+    let mut expr = expr;
+    let mut operator_line = None;
+    while let Expr::Logical(ref lhs, ref token, _) | Expr::Binary(ref lhs, ref token, _) = *expr {
+        operator_line = Some(token.line);
+        expr = lhs;
+    }
+    let line = match *expr {
+        Expr::Assign(ref name, _, _) | Expr::Variable(ref name, _) | Expr::Unary(ref name, _) => Some(name.line),
+        Expr::Literal(_) | Expr::Logical(..) | Expr::Binary(..) => None,
+        Expr::Call(ref callee, ref paren, _) => expr_line(callee).or(Some(paren.line)),
+        Expr::Grouping(ref e) => expr_line(e),
+    };
+    line.or(operator_line)
+}
+
+/// The line a statement starts on, if it can be recovered from its tokens.
+pub fn stmt_line(stmt: &Stmt) -> Option<i32> {
+    match *stmt {
+        Stmt::Block(ref statements) => statements.first().and_then(|stmt| stmt_line(stmt)),
+        Stmt::Expression(ref expr) => expr_line(expr),
+        Stmt::If(ref keyword, ..) |
+        Stmt::Print(ref keyword, _) |
+        Stmt::Var(ref keyword, _, _) |
+        Stmt::While(ref keyword, ..) => Some(keyword.line),
+    }
+}
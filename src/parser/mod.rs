@@ -1,3 +1,18 @@
+// The AST keeps the book's names and boxes every node, as generate_ast.py emits it.
+#[allow(clippy::upper_case_acronyms, clippy::vec_box)]
 pub mod ast;
 pub mod ast_printer;
+pub mod chain;
+pub mod location;
+// The parser lives in `parser::parser`, alongside the AST it builds.
+#[allow(clippy::module_inception)]
 pub mod parser;
+
+/// Scans and parses source that a test expects to be valid.
+#[cfg(test)]
+pub fn parse(source: &str) -> ast::AST {
+    use scanner::Scanner;
+
+    let tokens = Scanner::new(source.to_owned()).scan_tokens();
+    parser::Parser::new(tokens).parse().expect("source should parse")
+}
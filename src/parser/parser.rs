@@ -1,5 +1,8 @@
+use std::cell::Cell;
+
 use scanner::{Literal, Token, TokenType};
 use parser::ast::{Expr, Stmt, AST};
+use limits::MAX_NESTING;
 
 macro_rules! binary {
     ($self:expr, $func:expr, $token_types:expr) => {{
@@ -17,35 +20,72 @@ macro_rules! binary {
 pub struct Parser {
     tokens: Vec<Token>,
     current: usize,
+    depth: usize,
+    errors: Vec<ParseError>,
 }
 
 impl Parser {
-    pub fn new(tokens: Vec<Token>) -> Self {
+    /// Creates a parser over a token stream, ending it with an `EOF` token if
+    /// it doesn't already have one.
+    pub fn new(mut tokens: Vec<Token>) -> Self {
+        if tokens.last().is_none_or(|token| token.token_type != TokenType::EOF) {
+            let (line, column) = tokens.last().map_or((1, 1), |token| (token.line, token.column));
+            tokens.push(Token::new(TokenType::EOF, "", Literal::Nil, line, column));
+        }
         Parser {
             tokens,
             current: 0,
+            depth: 0,
+            errors: Vec::new(),
+        }
+    }
+
+    /// Parses the whole token stream, recovering after each error so that every
+    /// error in the source is reported rather than just the first.
+    pub fn parse(&mut self) -> Result<AST, Vec<ParseError>> {
+        let (ast, errors) = self.parse_recovering();
+        if errors.is_empty() {
+            Ok(ast)
+        } else {
+            Err(errors)
         }
     }
 
-    pub fn parse(&mut self) -> ParseResult<AST> {
+    /// Like `parse`, but also returns the statements that parsed successfully
+    /// when there were errors, for tools that work with incomplete code.
+    pub fn parse_recovering(&mut self) -> (AST, Vec<ParseError>) {
         let mut statements = Vec::new();
         while !self.is_at_end() {
-            statements.push(self.declaration()?);
+            if let Some(statement) = self.declaration() {
+                statements.push(statement);
+            }
         }
-        Ok(AST { root: statements })
+        (AST { root: statements }, self.errors.drain(..).collect())
     }
 
-    fn declaration(&mut self) -> ParseResult<Box<Stmt>> {
+    /// Parses a single expression that must make up the whole token stream, as
+    /// typed at a debugger prompt.
+    pub fn parse_expression(&mut self) -> Result<Box<Expr>, ParseError> {
+        let expr = self.expression()?;
+        if !self.is_at_end() {
+            let token = self.peek().clone();
+            return Err(self.error(&token, "Expect end of expression."));
+        }
+        Ok(expr)
+    }
+
+    fn declaration(&mut self) -> Option<Box<Stmt>> {
         let result = if self.match_token(&[TokenType::VAR]) {
             self.var_declaration()
         } else {
             self.statement()
         };
         match result {
-            Ok(_) => result,
-            Err(_) => {
+            Ok(statement) => Some(statement),
+            Err(err) => {
+                self.errors.push(err);
                 self.synchronize();
-                result
+                None
             }
         }
     }
@@ -62,10 +102,14 @@ impl Parser {
             TokenType::SEMICOLON,
             "Expect ';' after value.",
         )?;
-        Ok(Box::new(Stmt::Var(name.clone(), initializer)))
+        Ok(Box::new(Stmt::Var(name.clone(), initializer, Cell::default())))
     }
 
     fn statement(&mut self) -> ParseResult<Box<Stmt>> {
+        self.nested(Self::statement_kind)
+    }
+
+    fn statement_kind(&mut self) -> ParseResult<Box<Stmt>> {
         if self.match_token(&[TokenType::FOR]) {
             return self.for_statement();
         }
@@ -79,12 +123,13 @@ impl Parser {
             return self.while_statement();
         }
         if self.match_token(&[TokenType::LEFT_BRACE]) {
-            return Ok(Box::new(Stmt::Block(self.block()?)));
+            return self.block();
         }
         self.expression_statement()
     }
 
     fn for_statement(&mut self) -> ParseResult<Box<Stmt>> {
+        let keyword = self.previous().clone();
         self.consume_token(TokenType::LEFT_PAREN, "Expect '(' after 'for'.")?;
 
         let maybe_initializer = if self.match_token(&[TokenType::SEMICOLON]) {
@@ -120,7 +165,7 @@ impl Parser {
             Some(condition) => condition,
             None => Box::new(Expr::Literal(Literal::Boolean(true)))
         };
-        body = Box::new(Stmt::While(condition, body));
+        body = Box::new(Stmt::While(keyword, condition, body));
 
         if let Some(initializer) = maybe_initializer {
             body = Box::new(Stmt::Block(vec![initializer, body]));
@@ -130,6 +175,7 @@ impl Parser {
     }
 
     fn if_statement(&mut self) -> ParseResult<Box<Stmt>> {
+        let keyword = self.previous().clone();
         self.consume_token(TokenType::LEFT_PAREN, "Expect '(' after 'if'.")?;
         let condition = self.expression()?;
         self.consume_token(TokenType::RIGHT_PAREN, "Expect ')' after if condition.")?;
@@ -141,33 +187,37 @@ impl Parser {
             None
         };
 
-        Ok(Box::new(Stmt::If(condition, then_branch, else_branch)))
+        Ok(Box::new(Stmt::If(keyword, condition, then_branch, else_branch)))
     }
 
     fn print_statement(&mut self) -> ParseResult<Box<Stmt>> {
+        let keyword = self.previous().clone();
         let value = self.expression()?;
         self.consume_token(
             TokenType::SEMICOLON,
             "Expect ';' after value.",
         )?;
-        Ok(Box::new(Stmt::Print(value)))
+        Ok(Box::new(Stmt::Print(keyword, value)))
     }
 
     fn while_statement(&mut self) -> ParseResult<Box<Stmt>> {
+        let keyword = self.previous().clone();
         self.consume_token(TokenType::LEFT_PAREN, "Expect '(' after 'while'.")?;
         let condition = self.expression()?;
         self.consume_token(TokenType::RIGHT_PAREN, "Expect ')' after condition.")?;
         let body = self.statement()?;
-        Ok(Box::new(Stmt::While(condition, body)))
+        Ok(Box::new(Stmt::While(keyword, condition, body)))
     }
 
-    fn block(&mut self) -> ParseResult<Vec<Box<Stmt>>> {
+    fn block(&mut self) -> ParseResult<Box<Stmt>> {
         let mut statements = Vec::new();
         while !self.check(&TokenType::RIGHT_BRACE) && !self.is_at_end() {
-            statements.push(self.declaration()?);
+            if let Some(statement) = self.declaration() {
+                statements.push(statement);
+            }
         }
         self.consume_token(TokenType::RIGHT_BRACE, "Expect '}' after block.")?;
-        Ok(statements)
+        Ok(Box::new(Stmt::Block(statements)))
     }
 
     fn expression_statement(&mut self) -> ParseResult<Box<Stmt>> {
@@ -180,7 +230,7 @@ impl Parser {
     }
 
     fn expression(&mut self) -> ParseResult<Box<Expr>> {
-        self.assignment()
+        self.nested(Self::assignment)
     }
 
     fn assignment(&mut self) -> ParseResult<Box<Expr>> {
@@ -188,10 +238,10 @@ impl Parser {
 
         if self.match_token(&[TokenType::EQUAL]) {
             let equals = self.previous().clone();
-            let value = self.assignment()?;
+            let value = self.nested(Self::assignment)?;
 
-            if let Expr::Variable(name) = *expr {
-                return Ok(Box::new(Expr::Assign(name, value)));
+            if let Expr::Variable(ref name, ref binding) = *expr {
+                return Ok(Box::new(Expr::Assign(name.clone(), value, binding.clone())));
             }
 
             return Err(self.error(&equals, "Invalid assignment target."));
@@ -251,13 +301,13 @@ impl Parser {
     }
 
     fn multiplication(&mut self) -> ParseResult<Box<Expr>> {
-        binary!(self, self.unary(), &[TokenType::SLASH, TokenType::STAR])
+        binary!(self, self.unary(), &[TokenType::SLASH, TokenType::STAR, TokenType::TILDE_SLASH, TokenType::PERCENT])
     }
 
     fn unary(&mut self) -> ParseResult<Box<Expr>> {
         if self.match_token(&[TokenType::BANG, TokenType::MINUS]) {
             let operator = self.previous().clone();
-            let right = self.unary();
+            let right = self.nested(Self::unary);
             return Ok(Box::new(Expr::Unary(operator, right?)));
         }
         self.call()
@@ -265,9 +315,16 @@ impl Parser {
 
     fn call(&mut self) -> ParseResult<Box<Expr>> {
         let mut expr = self.primary()?;
+        // Each call in a chain like `f()()` nests the ones before it a level deeper.
+        let mut calls = 0;
 
         loop {
             if self.match_token(&[TokenType::LEFT_PAREN]) {
+                calls += 1;
+                if self.depth + calls >= MAX_NESTING {
+                    let paren = self.previous().clone();
+                    return Err(self.error(&paren, "Stack overflow."));
+                }
                 expr = self.finish_call(expr)?;
             } else {
                 break;
@@ -311,7 +368,7 @@ impl Parser {
             };
 
         if self.match_token(&[TokenType::IDENTIFIER]) {
-            return Ok(Box::new(Expr::Variable(self.previous().clone())));
+            return Ok(Box::new(Expr::Variable(self.previous().clone(), Cell::default())));
         }
 
         if self.match_token(&[TokenType::LEFT_PAREN]) {
@@ -370,6 +427,19 @@ impl Parser {
         self.tokens.get(self.current - 1).unwrap()
     }
 
+    /// Runs one of the mutually recursive parsing functions a level deeper,
+    /// failing rather than overflowing the stack on absurdly nested code.
+    fn nested<T, F: FnOnce(&mut Self) -> ParseResult<T>>(&mut self, parse: F) -> ParseResult<T> {
+        if self.depth >= MAX_NESTING {
+            let token = self.peek().clone();
+            return Err(self.error(&token, "Stack overflow."));
+        }
+        self.depth += 1;
+        let result = parse(self);
+        self.depth -= 1;
+        result
+    }
+
     fn error(&self, token: &Token, message: &str) -> ParseError {
         ParseError::new(token, message)
     }
@@ -396,7 +466,7 @@ impl Parser {
 
 type ParseResult<T> = Result<T, ParseError>;
 
-
+#[derive(Debug)]
 pub struct ParseError {
     pub token: Token,
     pub message: String,
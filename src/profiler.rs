@@ -0,0 +1,218 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use interpreter::{ExecutionHook, Interpreter, RuntimeResult};
+use lox_object::Callable;
+use parser::ast::Stmt;
+use parser::location::stmt_line;
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum FrameKind {
+    Line(i32),
+    Callable(String),
+    /// Blocks are not profiled on their own but still need a frame so that
+    /// every `after_statement` has something to pop.
+    Block,
+}
+
+struct Frame {
+    kind: FrameKind,
+    start: Instant,
+    children: Duration,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ProfileStats {
+    pub count: u64,
+    /// Wall time spent running a line or callable, including everything it
+    /// ran in turn, but counted once when a line runs inside itself.
+    pub total: Duration,
+}
+
+#[derive(Default)]
+struct Profile {
+    lines: HashMap<i32, ProfileStats>,
+    callables: HashMap<String, ProfileStats>,
+    /// Time spent in each stack of lines and callables, not counting nested
+    /// frames, keyed by the stack in the collapsed format.
+    stacks: HashMap<String, Duration>,
+    frames: Vec<Frame>,
+}
+
+/// Counts how often each source line and callable runs and how long it takes.
+/// Clones share the same profile, so one can be installed as the interpreter's
+/// hook and another kept to read the results once the program has finished.
+#[derive(Clone, Default)]
+pub struct Profiler {
+    profile: Rc<RefCell<Profile>>,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Each line that ran and its stats, the slowest first.
+    pub fn lines(&self) -> Vec<(i32, ProfileStats)> {
+        let mut lines: Vec<(i32, ProfileStats)> =
+            self.profile.borrow().lines.iter().map(|(&line, &stats)| (line, stats)).collect();
+        lines.sort_by(|a, b| b.1.total.cmp(&a.1.total).then(a.0.cmp(&b.0)));
+        lines
+    }
+
+    /// Each callable that was called and its stats, the slowest first.
+    pub fn callables(&self) -> Vec<(String, ProfileStats)> {
+        let mut callables: Vec<(String, ProfileStats)> = self
+            .profile
+            .borrow()
+            .callables
+            .iter()
+            .map(|(name, &stats)| (name.to_owned(), stats))
+            .collect();
+        callables.sort_by(|a, b| b.1.total.cmp(&a.1.total).then(a.0.cmp(&b.0)));
+        callables
+    }
+
+    /// A table of the lines and callables, the slowest first.
+    pub fn report(&self) -> String {
+        let mut report = String::new();
+        writeln!(report, "{:>8} {:>10} {:>12}", "line", "count", "total ms").unwrap();
+        for (line, stats) in self.lines() {
+            writeln!(report, "{:>8} {:>10} {:>12.3}", line, stats.count, millis(stats.total)).unwrap();
+        }
+        writeln!(report, "\n{:<16} {:>10} {:>12}", "callable", "calls", "total ms").unwrap();
+        for (name, stats) in self.callables() {
+            writeln!(report, "{:<16} {:>10} {:>12.3}", name, stats.count, millis(stats.total)).unwrap();
+        }
+        report
+    }
+
+    /// The time spent in each stack as `frame;frame;frame microseconds` lines,
+    /// which flamegraph tools read as collapsed stacks.
+    pub fn collapsed_stacks(&self) -> String {
+        let profile = self.profile.borrow();
+        let mut stacks: Vec<(&String, &Duration)> = profile.stacks.iter().collect();
+        stacks.sort();
+        let mut output = String::new();
+        for (stack, time) in stacks {
+            writeln!(output, "{} {}", stack, time.as_micros()).unwrap();
+        }
+        output
+    }
+}
+
+impl Profile {
+    fn push(&mut self, kind: FrameKind) {
+        self.frames.push(Frame { kind, start: Instant::now(), children: Duration::default() });
+    }
+
+    fn pop(&mut self) {
+        let frame = match self.frames.pop() {
+            Some(frame) => frame,
+            None => return,
+        };
+        let elapsed = frame.start.elapsed();
+        if let Some(parent) = self.frames.last_mut() {
+            parent.children += elapsed;
+        }
+
+        let recursive = self.frames.iter().any(|outer| outer.kind == frame.kind);
+        let stats = match frame.kind {
+            FrameKind::Line(line) => self.lines.entry(line).or_default(),
+            FrameKind::Callable(ref name) => self.callables.entry(name.to_owned()).or_default(),
+            FrameKind::Block => return,
+        };
+        stats.count += 1;
+        if !recursive {
+            stats.total += elapsed;
+        }
+
+        let mut stack = String::from("<script>");
+        for outer in self.frames.iter().chain(Some(&frame)) {
+            match outer.kind {
+                FrameKind::Line(line) => write!(stack, ";line {}", line).unwrap(),
+                FrameKind::Callable(ref name) => write!(stack, ";{}", name).unwrap(),
+                FrameKind::Block => {}
+            }
+        }
+        *self.stacks.entry(stack).or_default() += elapsed.saturating_sub(frame.children);
+    }
+}
+
+impl ExecutionHook for Profiler {
+    fn before_statement(&mut self, _interpreter: &mut Interpreter, stmt: &Stmt) -> RuntimeResult<()> {
+        let kind = match (stmt, stmt_line(stmt)) {
+            (&Stmt::Block(_), _) | (_, None) => FrameKind::Block,
+            (_, Some(line)) => FrameKind::Line(line),
+        };
+        self.profile.borrow_mut().push(kind);
+        Ok(())
+    }
+
+    fn after_statement(&mut self, _stmt: &Stmt) {
+        self.profile.borrow_mut().pop();
+    }
+
+    fn before_call(&mut self, callee: &dyn Callable) {
+        self.profile.borrow_mut().push(FrameKind::Callable(callee.name().to_owned()));
+    }
+
+    fn after_call(&mut self, _callee: &dyn Callable) {
+        self.profile.borrow_mut().pop();
+    }
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use lox::Lox;
+    use output::OutputBuffer;
+
+    fn profile(source: &str) -> Profiler {
+        let profiler = Profiler::new();
+        let mut lox = Lox::new();
+        lox.set_output(OutputBuffer::new());
+        lox.set_hook(profiler.clone());
+        lox.run(source).expect("program should run");
+        profiler
+    }
+
+    #[test]
+    fn test_counts_lines_and_calls() {
+        let profiler = profile("var i = 0;\nwhile (i < 3) {\n  i = i + 1;\n  clock();\n}\nprint i;");
+        let mut counts: Vec<(i32, u64)> = profiler.lines().iter().map(|&(line, stats)| (line, stats.count)).collect();
+        counts.sort();
+        assert_eq!(counts, vec![(1, 1), (2, 1), (3, 3), (4, 3), (6, 1)]);
+
+        let callables = profiler.callables();
+        assert_eq!(callables.len(), 1);
+        assert_eq!(callables[0].0, "clock");
+        assert_eq!(callables[0].1.count, 3);
+
+        let lines = profiler.lines();
+        let total = |line| lines.iter().find(|&&(l, _)| l == line).unwrap().1.total;
+        assert!(total(2) >= total(3) + total(4));
+        assert!(profiler.report().contains("clock"));
+    }
+
+    #[test]
+    fn test_collapsed_stacks() {
+        let profiler = profile("var i = 0;\nwhile (i < 2) i = i + clock() * 0 + 1;");
+        let stacks: Vec<String> = profiler
+            .collapsed_stacks()
+            .lines()
+            .map(|line| line.rsplit_once(' ').unwrap().0.to_owned())
+            .collect();
+        assert_eq!(
+            stacks,
+            vec!["<script>;line 1", "<script>;line 2", "<script>;line 2;line 2", "<script>;line 2;line 2;clock"]
+        );
+    }
+}
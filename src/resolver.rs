@@ -0,0 +1,279 @@
+use std::collections::HashMap;
+
+use limits::MAX_NESTING;
+use parser::ast::{Expr, ExprVisitor, Stmt, StmtVisitor, AST};
+use parser::chain;
+use symbol::Symbol;
+
+/// Where a variable lives: in the globals, looked up by name, or in the slot
+/// of a block's environment `depth` blocks out from the one it's used in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Binding {
+    Global,
+    Local { depth: usize, slot: usize },
+}
+
+/// Nodes start out global, which is also where they stay if the resolver never
+/// reaches them.
+impl Default for Binding {
+    fn default() -> Self {
+        Binding::Global
+    }
+}
+
+/// Resolves each variable to the global or local slot it refers to, following
+/// the same scoping the interpreter does: a `var` in a block takes the next
+/// slot of that block, unless it redeclares a name the block already has, and
+/// its initializer still sees any variable it shadows. Bindings are stored in
+/// the nodes themselves.
+#[derive(Default)]
+pub struct Resolver {
+    scopes: Vec<HashMap<Symbol, usize>>,
+    depth: usize,
+}
+
+impl Resolver {
+    pub fn new() -> Self {
+        Resolver { scopes: Vec::new(), depth: 0 }
+    }
+
+    /// Resolves within blocks that already have the given locals, outermost
+    /// first, each in slot order, as when evaluating an expression in a paused
+    /// program.
+    pub fn with_scopes(scopes: Vec<Vec<Symbol>>) -> Self {
+        let scopes = scopes
+            .into_iter()
+            .map(|names| names.into_iter().enumerate().map(|(slot, name)| (name, slot)).collect())
+            .collect();
+        Resolver { scopes, depth: 0 }
+    }
+
+    pub fn resolve(&mut self, ast: &AST) {
+        for statement in ast.root.iter() {
+            self.visit_stmt(statement);
+        }
+    }
+
+    pub fn resolve_expression(&mut self, expr: &Expr) {
+        self.visit_expr(expr);
+    }
+
+    /// Resolves a child node, unless it's nested deeper than the interpreter
+    /// will ever run, which also keeps the resolver from overflowing the stack.
+    fn nested<F: FnOnce(&mut Self)>(&mut self, resolve: F) {
+        if self.depth < MAX_NESTING {
+            self.depth += 1;
+            resolve(self);
+            self.depth -= 1;
+        }
+    }
+
+    fn declare(&mut self, name: &Symbol) -> Binding {
+        match self.scopes.last_mut() {
+            Some(scope) => {
+                let next = scope.len();
+                let slot = *scope.entry(name.clone()).or_insert(next);
+                Binding::Local { depth: 0, slot }
+            }
+            None => Binding::Global,
+        }
+    }
+
+    fn look_up(&self, name: &Symbol) -> Binding {
+        for (depth, scope) in self.scopes.iter().rev().enumerate() {
+            if let Some(&slot) = scope.get(name) {
+                return Binding::Local { depth, slot };
+            }
+        }
+        Binding::Global
+    }
+}
+
+impl StmtVisitor<()> for Resolver {
+    fn visit_stmt(&mut self, stmt: &Stmt) {
+        self.nested(|resolver| resolver.resolve_stmt(stmt));
+    }
+}
+
+impl ExprVisitor<()> for Resolver {
+    fn visit_expr(&mut self, expr: &Expr) {
+        self.nested(|resolver| resolver.resolve_expr(expr));
+    }
+}
+
+impl Resolver {
+    fn resolve_stmt(&mut self, stmt: &Stmt) {
+        match *stmt {
+            Stmt::Block(ref statements) => {
+                self.scopes.push(HashMap::new());
+                for statement in statements {
+                    self.visit_stmt(statement);
+                }
+                self.scopes.pop();
+            }
+            Stmt::Expression(ref expr) | Stmt::Print(_, ref expr) => self.visit_expr(expr),
+            Stmt::If(_, ref condition, ref then_clause, ref maybe_else_clause) => {
+                self.visit_expr(condition);
+                self.visit_stmt(then_clause);
+                if let Some(ref else_clause) = *maybe_else_clause {
+                    self.visit_stmt(else_clause);
+                }
+            }
+            Stmt::While(_, ref condition, ref body) => {
+                self.visit_expr(condition);
+                self.visit_stmt(body);
+            }
+            Stmt::Var(ref name, ref initializer, ref binding) => {
+                self.visit_expr(initializer);
+                binding.set(self.declare(&name.lexeme));
+            }
+        }
+    }
+
+    fn resolve_expr(&mut self, expr: &Expr) {
+        match *expr {
+            Expr::Literal(_) => {}
+            Expr::Logical(..) | Expr::Binary(..) => {
+                let (operand, links) = chain::split(expr);
+                self.visit_expr(operand);
+                for link in links {
+                    self.visit_expr(link.right);
+                }
+            }
+            Expr::Call(ref callee, _, ref arguments) => {
+                self.visit_expr(callee);
+                for argument in arguments {
+                    self.visit_expr(argument);
+                }
+            }
+            Expr::Unary(_, ref e) | Expr::Grouping(ref e) => self.visit_expr(e),
+            Expr::Variable(ref name, ref binding) => binding.set(self.look_up(&name.lexeme)),
+            Expr::Assign(ref name, ref value, ref binding) => {
+                self.visit_expr(value);
+                binding.set(self.look_up(&name.lexeme));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::cell::Cell;
+    use parser;
+    use scanner::{Literal, Token, TokenType};
+
+    fn resolve(source: &str) -> AST {
+        let ast = parser::parse(source);
+        Resolver::new().resolve(&ast);
+        ast
+    }
+
+    fn variable(expr: &Expr) -> Binding {
+        match *expr {
+            Expr::Variable(_, ref binding) | Expr::Assign(_, _, ref binding) => binding.get(),
+            _ => panic!("expected a variable"),
+        }
+    }
+
+    fn declaration(stmt: &Stmt) -> Binding {
+        match *stmt {
+            Stmt::Var(_, _, ref binding) => binding.get(),
+            _ => panic!("expected a var statement"),
+        }
+    }
+
+    fn print_binding(stmt: &Stmt) -> Binding {
+        match *stmt {
+            Stmt::Print(_, ref expr) => variable(expr),
+            _ => panic!("expected a print statement"),
+        }
+    }
+
+    #[test]
+    fn test_resolves_locals_to_slots() {
+        let ast = resolve("var g = 0; { var a = 1; var b = 2; { var c = a; print b; print c; print g; } }");
+        assert_eq!(declaration(&ast.root[0]), Binding::Global);
+        let outer = match *ast.root[1] {
+            Stmt::Block(ref statements) => statements,
+            _ => panic!("expected a block"),
+        };
+        assert_eq!(declaration(&outer[1]), Binding::Local { depth: 0, slot: 1 });
+        let inner = match *outer[2] {
+            Stmt::Block(ref statements) => statements,
+            _ => panic!("expected a block"),
+        };
+        assert_eq!(print_binding(&inner[1]), Binding::Local { depth: 1, slot: 1 });
+        assert_eq!(print_binding(&inner[2]), Binding::Local { depth: 0, slot: 0 });
+        assert_eq!(print_binding(&inner[3]), Binding::Global);
+    }
+
+    #[test]
+    fn test_initializer_sees_shadowed_variable() {
+        let ast = resolve("{ var a = 1; { var a = a; print a; var a = 2; } }");
+        let inner = match *ast.root[0] {
+            Stmt::Block(ref statements) => match *statements[1] {
+                Stmt::Block(ref statements) => statements,
+                _ => panic!("expected a block"),
+            },
+            _ => panic!("expected a block"),
+        };
+        match *inner[0] {
+            Stmt::Var(_, ref initializer, _) => {
+                assert_eq!(variable(initializer), Binding::Local { depth: 1, slot: 0 })
+            }
+            _ => panic!("expected a var statement"),
+        }
+        assert_eq!(print_binding(&inner[1]), Binding::Local { depth: 0, slot: 0 });
+        // Redeclaring reuses the slot.
+        assert_eq!(declaration(&inner[2]), Binding::Local { depth: 0, slot: 0 });
+    }
+
+    #[test]
+    fn test_stops_below_nesting_limit() {
+        // The parser rejects groupings this deep, so the tree is built by hand.
+        let name = Token::new(TokenType::IDENTIFIER, "a", Literal::Nil, 1, 1);
+        let plus = Token::new(TokenType::PLUS, "+", Literal::Nil, 1, 1);
+        let print = Token::new(TokenType::PRINT, "print", Literal::Nil, 1, 1);
+        let reference = || Box::new(Expr::Variable(name.clone(), Cell::default()));
+        let deep = (0..MAX_NESTING + 10).fold(reference(), |expr, _| Box::new(Expr::Grouping(expr)));
+        let ast = AST {
+            root: vec![Box::new(Stmt::Block(vec![
+                Box::new(Stmt::Var(name.clone(), Box::new(Expr::Literal(Literal::Integer(1))), Cell::default())),
+                Box::new(Stmt::Print(print, Box::new(Expr::Binary(reference(), plus, deep)))),
+            ]))],
+        };
+        Resolver::new().resolve(&ast);
+        let (shallow, mut deep) = match *ast.root[0] {
+            Stmt::Block(ref statements) => match *statements[1] {
+                Stmt::Print(_, ref expr) => match **expr {
+                    Expr::Binary(ref lhs, _, ref rhs) => (lhs, rhs),
+                    _ => panic!("expected a binary expression"),
+                },
+                _ => panic!("expected a print statement"),
+            },
+            _ => panic!("expected a block"),
+        };
+        while let Expr::Grouping(ref e) = **deep {
+            deep = e;
+        }
+        assert_eq!(variable(shallow), Binding::Local { depth: 0, slot: 0 });
+        assert_eq!(variable(deep), Binding::Global);
+    }
+
+    #[test]
+    fn test_resolves_every_operand_of_a_long_chain() {
+        let chain = vec!["a"; MAX_NESTING * 4].join(" + ");
+        let ast = resolve(&format!("{{ var a = 1; print {}; }}", chain));
+        let expr = match *ast.root[0] {
+            Stmt::Block(ref statements) => match *statements[1] {
+                Stmt::Print(_, ref expr) => expr,
+                _ => panic!("expected a print statement"),
+            },
+            _ => panic!("expected a block"),
+        };
+        let (operand, links) = chain::split(expr);
+        assert_eq!(variable(operand), Binding::Local { depth: 0, slot: 0 });
+        assert!(links.iter().all(|link| variable(link.right) == Binding::Local { depth: 0, slot: 0 }));
+    }
+}
@@ -1,14 +1,19 @@
 use std::fmt;
 use std::collections::HashMap;
+use std::rc::Rc;
 
 use lox_object::LoxObject;
+use symbol::Symbol;
 
 pub struct Scanner {
     source: String,
     tokens: Vec<Token>,
+    errors: Vec<ScanError>,
     start: usize,
     current: usize,
     line: i32,
+    line_start: usize,
+    column: i32,
 }
 
 impl Scanner {
@@ -16,22 +21,36 @@ impl Scanner {
         Scanner {
             source,
             tokens: Vec::new(),
+            errors: Vec::new(),
             start: 0,
             current: 0,
             line: 1,
+            line_start: 0,
+            column: 1,
         }
     }
 
     pub fn scan_tokens(&mut self) -> Vec<Token> {
+        self.scan(false)
+    }
+
+    /// Like `scan_tokens`, but keeps the comment, whitespace and newline tokens
+    /// that are normally discarded.
+    pub fn scan_tokens_with_trivia(&mut self) -> Vec<Token> {
+        self.scan(true)
+    }
+
+    fn scan(&mut self, include_trivia: bool) -> Vec<Token> {
         while !self.is_at_end() {
             self.start = self.current;
+            self.column = (self.start - self.line_start) as i32 + 1;
             match self.scan_token() {
                 Ok(token) => {
-                    if !token.token_type.is_ignored() {
+                    if include_trivia || !token.token_type.is_ignored() {
                         self.tokens.push(token);
                     }
                 }
-                Err(_) => {}
+                Err(err) => self.errors.push(err),
             }
         }
         self.tokens.push(Token::new(
@@ -39,10 +58,15 @@ impl Scanner {
             "",
             Literal::Nil,
             self.line,
+            (self.current - self.line_start) as i32 + 1,
         ));
         self.tokens.to_vec()
     }
 
+    pub fn errors(&self) -> &[ScanError] {
+        &self.errors
+    }
+
     fn is_at_end(&self) -> bool {
         self.current >= self.source.len()
     }
@@ -60,6 +84,15 @@ impl Scanner {
             '+' => Ok(self.create_token(TokenType::PLUS)),
             ';' => Ok(self.create_token(TokenType::SEMICOLON)),
             '*' => Ok(self.create_token(TokenType::STAR)),
+            '%' => Ok(self.create_token(TokenType::PERCENT)),
+            '~' => {
+                if self.match_char('/') {
+                    self.advance();
+                    Ok(self.create_token(TokenType::TILDE_SLASH))
+                } else {
+                    Err(ScanError::new(self.line, self.column, "Unexpected character."))
+                }
+            }
             // TODO: A bit of duplication here. Should refactor at some point.
             '!' => {
                 if self.match_char('=') {
@@ -105,26 +138,36 @@ impl Scanner {
             }
             ' ' | '\r' | '\t' => Ok(self.create_token(TokenType::WHITESPACE)),
             '\n' => {
-                self.line += 1;
-                Ok(self.create_token(TokenType::NEWLINE))
+                // The newline belongs to the line it ends.
+                let token = self.create_token(TokenType::NEWLINE);
+                let next_line_start = self.current;
+                self.new_line(next_line_start);
+                Ok(token)
             }
             '"' => self.scan_string(),
-            c if c.is_digit(10) => self.scan_number(),
-            c if c.is_alphabetic() => self.scan_identifier(),
-            _ => Err(ScanError::new(self.line, "Unexpected character.")),
+            c if c.is_ascii_digit() => self.scan_number(),
+            c if c.is_ascii_alphabetic() => self.scan_identifier(),
+            _ => {
+                // Skip the rest of a multi-byte character so it's reported once.
+                while !self.source.is_char_boundary(self.current) {
+                    self.current += 1;
+                }
+                Err(ScanError::new(self.line, self.column, "Unexpected character."))
+            }
         }
     }
 
     fn scan_string(&mut self) -> ScanResult<Token> {
         while self.peek() != '"' && !self.is_at_end() {
             if self.peek() == '\n' {
-                self.line += 1;
+                let next_line_start = self.current + 1;
+                self.new_line(next_line_start);
             }
             self.advance();
         }
         // Unterminated string.
         if self.is_at_end() {
-            return Err(ScanError::new(self.line, "Unterminated string."));
+            return Err(ScanError::new(self.line, self.column, "Unterminated string."));
         }
 
         // The closing ".
@@ -134,32 +177,44 @@ impl Scanner {
         let value = &self.source[self.start + 1..self.current - 1];
         Ok(self.create_token_with_literal(
             TokenType::STRING,
-            Literal::String(value.to_owned()),
+            Literal::String(Rc::clone(Symbol::intern(value).as_rc())),
         ))
     }
 
+    /// Scans an integer, or a float if the number has a fractional part.
     fn scan_number(&mut self) -> ScanResult<Token> {
-        while self.peek().is_digit(10) {
+        while self.peek().is_ascii_digit() {
             self.advance();
         }
 
         // Look for a fractional part.
-        if self.peek() == '.' && self.peek_next().is_digit(10) {
+        if self.peek() == '.' && self.peek_next().is_ascii_digit() {
             // Consume the "."
             self.advance();
-            while self.peek().is_digit(10) {
+            while self.peek().is_ascii_digit() {
                 self.advance();
             }
+            let value = &self.source[self.start..self.current];
+            return Ok(self.create_token_with_literal(
+                TokenType::NUMBER,
+                Literal::Number(value.parse::<f64>().unwrap()),
+            ));
         }
         let value = &self.source[self.start..self.current];
-        Ok(self.create_token_with_literal(
-            TokenType::NUMBER,
-            Literal::Number(value.parse::<f64>().unwrap()),
-        ))
+        let literal = match value.parse::<i64>() {
+            Ok(n) => Literal::Integer(n),
+            Err(_) => {
+                // Still produce a number, so the parser doesn't report the
+                // expression it's missing as well.
+                self.errors.push(ScanError::new(self.line, self.column, "Integer literal is too large."));
+                Literal::Number(value.parse::<f64>().unwrap())
+            }
+        };
+        Ok(self.create_token_with_literal(TokenType::NUMBER, literal))
     }
 
     fn scan_identifier(&mut self) -> ScanResult<Token> {
-        while self.peek().is_alphanumeric() {
+        while self.peek().is_ascii_alphanumeric() {
             self.advance();
         }
 
@@ -207,31 +262,39 @@ impl Scanner {
 
     fn create_token_with_literal(&self, token_type: TokenType, literal: Literal) -> Token {
         let s = &self.source[self.start..self.current];
-        Token::new(token_type, s, literal, self.line)
+        Token::new(token_type, s, literal, self.line, self.column)
+    }
+
+    fn new_line(&mut self, next_line_start: usize) {
+        self.line += 1;
+        self.line_start = next_line_start;
     }
 }
 
 #[derive(Clone, Debug, PartialEq)]
 pub struct Token {
     pub token_type: TokenType,
-    pub lexeme: String,
+    pub lexeme: Symbol,
     pub literal: Literal,
     pub line: i32,
+    /// The 1-based column of the token's first character.
+    pub column: i32,
 }
 
 impl Token {
-    pub fn new(token_type: TokenType, lexeme: &str, literal: Literal, line: i32) -> Self {
+    pub fn new(token_type: TokenType, lexeme: &str, literal: Literal, line: i32, column: i32) -> Self {
         Token {
             token_type,
-            lexeme: lexeme.to_owned(),
+            lexeme: Symbol::intern(lexeme),
             literal,
             line,
+            column,
         }
     }
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
-#[allow(non_camel_case_types)]
+#[allow(non_camel_case_types, clippy::upper_case_acronyms)]
 pub enum TokenType {
     // Single-character tokens.
     LEFT_PAREN,
@@ -245,6 +308,7 @@ pub enum TokenType {
     SEMICOLON,
     SLASH,
     STAR,
+    PERCENT,
 
     // One or two character tokens.
     BANG,
@@ -255,6 +319,7 @@ pub enum TokenType {
     GREATER_EQUAL,
     LESS,
     LESS_EQUAL,
+    TILDE_SLASH,
 
     // Literals.
     IDENTIFIER,
@@ -288,21 +353,23 @@ pub enum TokenType {
 }
 
 impl TokenType {
-    fn is_ignored(&self) -> bool {
-        let n = *self as u8;
-        n > 38
+    pub fn is_ignored(&self) -> bool {
+        matches!(*self, TokenType::COMMENT | TokenType::WHITESPACE | TokenType::NEWLINE)
     }
 }
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum Literal {
-    String(String),
+    /// String literals in the source share the interned text of their value.
+    String(Rc<str>),
+    Integer(i64),
     Number(f64),
     Boolean(bool),
     Nil,
 }
 
 impl Literal {
+    #[allow(clippy::wrong_self_convention)]
     pub fn to_lox_object(self) -> LoxObject {
         LoxObject::Literal(self)
     }
@@ -312,6 +379,9 @@ impl fmt::Display for Literal {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             Literal::String(ref s) => write!(f, "{}", s),
+            Literal::Integer(n) => write!(f, "{}", n),
+            // Floats keep a fractional part, so that `3.0` doesn't read as `3`.
+            Literal::Number(n) if n.is_finite() && n.fract() == 0.0 => write!(f, "{}.0", n),
             Literal::Number(n) => write!(f, "{}", n),
             Literal::Boolean(b) => write!(f, "{}", b),
             Literal::Nil => write!(f, "nil"),
@@ -321,15 +391,18 @@ impl fmt::Display for Literal {
 
 type ScanResult<T> = Result<T, ScanError>;
 
+#[derive(Clone, Debug)]
 pub struct ScanError {
-    line: i32,
-    message: String,
+    pub line: i32,
+    pub column: i32,
+    pub message: String,
 }
 
 impl ScanError {
-    fn new(line: i32, message: &str) -> Self {
+    fn new(line: i32, column: i32, message: &str) -> Self {
         ScanError {
             line,
+            column,
             message: message.to_owned(),
         }
     }
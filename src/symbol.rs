@@ -0,0 +1,131 @@
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::ops::Deref;
+use std::rc::Rc;
+
+/// The interner is pruned of strings nothing refers to any more whenever it
+/// has doubled in size since it last was, starting from this many.
+const INITIAL_PRUNE_THRESHOLD: usize = 1024;
+
+/// An interned string. Every symbol with the same text shares one allocation,
+/// so symbols are cheap to clone and compare and hash by address.
+#[derive(Clone)]
+pub struct Symbol(Rc<str>);
+
+struct Interner {
+    strings: HashSet<Rc<str>>,
+    next_prune: usize,
+}
+
+thread_local! {
+    static INTERNER: RefCell<Interner> = RefCell::new(Interner {
+        strings: HashSet::new(),
+        next_prune: INITIAL_PRUNE_THRESHOLD,
+    });
+}
+
+impl Symbol {
+    pub fn intern(text: &str) -> Self {
+        INTERNER.with(|interner| {
+            let mut interner = interner.borrow_mut();
+            if let Some(string) = interner.strings.get(text) {
+                return Symbol(Rc::clone(string));
+            }
+            if interner.strings.len() >= interner.next_prune {
+                interner.strings.retain(|string| Rc::strong_count(string) > 1);
+                interner.next_prune = INITIAL_PRUNE_THRESHOLD.max(interner.strings.len() * 2);
+            }
+            let string: Rc<str> = Rc::from(text);
+            interner.strings.insert(Rc::clone(&string));
+            Symbol(string)
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// The shared text, for values that should reuse the interned allocation.
+    pub fn as_rc(&self) -> &Rc<str> {
+        &self.0
+    }
+}
+
+impl PartialEq for Symbol {
+    fn eq(&self, other: &Symbol) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for Symbol {}
+
+impl Hash for Symbol {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (self.0.as_ptr() as usize).hash(state)
+    }
+}
+
+impl PartialEq<str> for Symbol {
+    fn eq(&self, other: &str) -> bool {
+        &*self.0 == other
+    }
+}
+
+impl<'a> PartialEq<&'a str> for Symbol {
+    fn eq(&self, other: &&'a str) -> bool {
+        &*self.0 == *other
+    }
+}
+
+impl Deref for Symbol {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl AsRef<str> for Symbol {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&*self.0, f)
+    }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&*self.0, f)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_interned_symbols_share_text() {
+        let a = Symbol::intern("name");
+        let b = Symbol::intern(&String::from("name"));
+        assert!(Rc::ptr_eq(a.as_rc(), b.as_rc()));
+        assert_eq!(a, b);
+        assert_ne!(a, Symbol::intern("other"));
+        assert_eq!(a, "name");
+    }
+
+    #[test]
+    fn test_unused_symbols_are_pruned() {
+        for i in 0..INITIAL_PRUNE_THRESHOLD * 4 {
+            Symbol::intern(&format!("temporary {}", i));
+        }
+        let kept = Symbol::intern("kept");
+        INTERNER.with(|interner| assert!(interner.borrow().strings.len() <= INITIAL_PRUNE_THRESHOLD * 2));
+        assert!(Rc::ptr_eq(kept.as_rc(), Symbol::intern("kept").as_rc()));
+    }
+}
@@ -0,0 +1,104 @@
+use std::io;
+use std::io::prelude::*;
+
+use debugger::describe;
+use interpreter::{ExecutionHook, Interpreter, RuntimeError, RuntimeResult};
+use lox_object::LoxObject;
+use parser::ast::{Expr, ExprVisitor, Stmt};
+use parser::ast_printer::AstPrinter;
+use parser::location::stmt_line;
+
+/// Logs each statement as it runs, with its source line, followed by the
+/// variables it defines or assigns and, optionally, the value of every
+/// expression it evaluates.
+pub struct Tracer<W> {
+    lines: Vec<String>,
+    output: W,
+    trace_expressions: bool,
+}
+
+impl<W: Write> Tracer<W> {
+    pub fn new(source: &str, output: W, trace_expressions: bool) -> Self {
+        Tracer {
+            lines: source.lines().map(str::to_owned).collect(),
+            output,
+            trace_expressions,
+        }
+    }
+
+    fn write(&mut self, text: &str) -> io::Result<()> {
+        writeln!(self.output, "{}", text)
+    }
+}
+
+impl<W: Write> ExecutionHook for Tracer<W> {
+    fn before_statement(&mut self, _interpreter: &mut Interpreter, stmt: &Stmt) -> RuntimeResult<()> {
+        // A block adds nothing over the statements inside it, which are traced
+        // on their own.
+        if let Stmt::Block(_) = *stmt {
+            return Ok(());
+        }
+        if let Some(line) = stmt_line(stmt) {
+            let text = self.lines.get((line - 1) as usize).map_or("", |text| text.trim());
+            let entry = format!("[line {}] {}", line, text);
+            self.write(&entry).map_err(|err| RuntimeError::output_failed(line, err))?;
+        }
+        Ok(())
+    }
+
+    fn after_expression(&mut self, expr: &Expr, value: &LoxObject) -> io::Result<()> {
+        // Literals are their own value.
+        if !self.trace_expressions || matches!(*expr, Expr::Literal(_)) {
+            return Ok(());
+        }
+        let entry = format!("  {} => {}", AstPrinter::new().visit_expr(expr), describe(value));
+        self.write(&entry)
+    }
+
+    fn on_define(&mut self, name: &str, value: &LoxObject) -> io::Result<()> {
+        let entry = format!("  define {} = {}", name, describe(value));
+        self.write(&entry)
+    }
+
+    fn on_assign(&mut self, name: &str, value: &LoxObject) -> io::Result<()> {
+        let entry = format!("  assign {} = {}", name, describe(value));
+        self.write(&entry)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use lox::Lox;
+    use output::OutputBuffer;
+
+    fn trace(source: &str, trace_expressions: bool) -> String {
+        let trace = OutputBuffer::new();
+        let mut lox = Lox::new();
+        lox.set_output(OutputBuffer::new());
+        lox.set_hook(Tracer::new(source, trace.clone(), trace_expressions));
+        lox.run(source).expect("program should run");
+        trace.contents()
+    }
+
+    #[test]
+    fn test_traces_statements_and_variable_changes() {
+        let source = "var a = \"x\";\nif (a == \"x\") {\n  a = 1;\n}\nprint a;";
+        assert_eq!(
+            trace(source, false),
+            "[line 1] var a = \"x\";\n  define a = \"x\"\n\
+             [line 2] if (a == \"x\") {\n\
+             [line 3] a = 1;\n  assign a = 1\n\
+             [line 5] print a;\n"
+        );
+    }
+
+    #[test]
+    fn test_traces_expression_values() {
+        assert_eq!(
+            trace("var a = 1;\nprint -(a + 2);", true),
+            "[line 1] var a = 1;\n  define a = 1\n\
+             [line 2] print -(a + 2);\n  a => 1\n  (+ a 2) => 3\n  (group (+ a 2)) => 3\n  (- (group (+ a 2))) => -3\n"
+        );
+    }
+}
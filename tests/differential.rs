@@ -0,0 +1,420 @@
+//! Generates random Lox programs and runs each of them both as written and
+//! optimized, checking that the two print the same output and fail with the
+//! same error. The tree-walking interpreter is the only backend there is, so
+//! the optimizer stands in for a second one: this checks that folding and
+//! dead code removal don't change behavior, not that two independent
+//! implementations agree. The programs use variables, blocks, branches and
+//! loops that always finish, and now and then a runtime error on purpose.
+//!
+//! A failure reports the seeds of the programs that diverged, and the first of
+//! them shrunk to as few lines as still diverge; set `LOX_DIFFERENTIAL_SEED` to
+//! a seed to run only that program.
+
+extern crate rlox;
+
+use std::env;
+use std::time::Duration;
+
+use rlox::{Limit, Limits, Lox, LoxError, OutputBuffer};
+
+/// How many programs a run generates.
+const PROGRAMS: u64 = 500;
+
+/// Names shared between scopes, so that blocks shadow and redeclare them.
+const NAMES: &[&str] = &["a", "b", "c", "d", "e"];
+
+/// How deeply expressions and statements are nested.
+const MAX_EXPR_DEPTH: usize = 4;
+const MAX_STMT_DEPTH: usize = 3;
+
+/// The steps a program may take while it's being shrunk, where removing a loop
+/// counter's increment leaves a loop that never ends.
+const SHRINK_MAX_STEPS: u64 = 1_000_000;
+
+/// A small xorshift generator, so that a seed always makes the same program.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        // Xorshift never leaves zero, and nearby seeds should diverge quickly.
+        Rng(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    /// True with a probability of one in `n`.
+    fn one_in(&mut self, n: usize) -> bool {
+        self.below(n) == 0
+    }
+
+    fn choose<'a, T>(&mut self, items: &'a [T]) -> &'a T {
+        &items[self.below(items.len())]
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Type {
+    Number,
+    String,
+    Boolean,
+}
+
+const TYPES: &[Type] = &[Type::Number, Type::String, Type::Boolean];
+
+struct Generator {
+    rng: Rng,
+    /// The variables in scope, innermost scope last, with the type of the value
+    /// each holds and whether the program may assign to it.
+    scopes: Vec<Vec<(String, Type, bool)>>,
+    counters: usize,
+    source: String,
+}
+
+impl Generator {
+    fn new(seed: u64) -> Self {
+        Generator {
+            rng: Rng::new(seed),
+            scopes: vec![Vec::new()],
+            counters: 0,
+            source: String::new(),
+        }
+    }
+
+    fn program(mut self) -> String {
+        for _ in 0..self.rng.below(12) + 4 {
+            self.stmt(0);
+        }
+        self.source
+    }
+
+    fn stmt(&mut self, depth: usize) {
+        let choice = if depth >= MAX_STMT_DEPTH { self.rng.below(4) } else { self.rng.below(8) };
+        match choice {
+            0 | 1 => {
+                let expr = self.any_expr(0);
+                self.line(&format!("print {};", expr));
+            }
+            2 => self.var_declaration(),
+            3 => self.assignment(),
+            4 => {
+                self.line("{");
+                self.scopes.push(Vec::new());
+                self.stmts(depth);
+                self.scopes.pop();
+                self.line("}");
+            }
+            5 => {
+                let condition = self.condition();
+                self.line(&format!("if ({}) {{", condition));
+                self.block_body(depth);
+                if self.rng.one_in(2) {
+                    self.line("} else {");
+                    self.block_body(depth);
+                }
+                self.line("}");
+            }
+            6 => self.while_loop(depth),
+            _ => self.for_loop(depth),
+        }
+    }
+
+    fn stmts(&mut self, depth: usize) {
+        for _ in 0..self.rng.below(4) + 1 {
+            self.stmt(depth + 1);
+        }
+    }
+
+    fn block_body(&mut self, depth: usize) {
+        self.scopes.push(Vec::new());
+        self.stmts(depth);
+        self.scopes.pop();
+    }
+
+    fn var_declaration(&mut self) {
+        let ty = *self.rng.choose(TYPES);
+        // The initializer is resolved before the variable is declared.
+        let initializer = self.expr(ty, 0);
+        let name = self.rng.choose(NAMES).to_string();
+        self.line(&format!("var {} = {};", name, initializer));
+        let scope = self.scopes.last_mut().unwrap();
+        scope.retain(|variable| variable.0 != name);
+        scope.push((name, ty, true));
+    }
+
+    fn assignment(&mut self) {
+        let assignable: Vec<(String, Type)> = self
+            .visible()
+            .into_iter()
+            .filter(|variable| variable.2)
+            .map(|variable| (variable.0, variable.1))
+            .collect();
+        if assignable.is_empty() {
+            return self.var_declaration();
+        }
+        let (name, ty) = self.rng.choose(&assignable).clone();
+        let value = self.expr(ty, 0);
+        self.line(&format!("{} = {};", name, value));
+    }
+
+    /// A loop over a fresh counter that nothing else assigns, so it always ends.
+    fn while_loop(&mut self, depth: usize) {
+        let counter = self.counter();
+        let bound = self.rng.below(5);
+        self.line("{");
+        self.line(&format!("var {} = 0;", counter));
+        self.scopes.push(vec![(counter.clone(), Type::Number, false)]);
+        self.line(&format!("while ({} < {}) {{", counter, bound));
+        self.block_body(depth);
+        self.line(&format!("{} = {} + 1;", counter, counter));
+        self.line("}");
+        self.scopes.pop();
+        self.line("}");
+    }
+
+    fn for_loop(&mut self, depth: usize) {
+        let counter = self.counter();
+        let bound = self.rng.below(5);
+        self.line(&format!("for (var {0} = 0; {0} < {1}; {0} = {0} + 1) {{", counter, bound));
+        self.scopes.push(vec![(counter, Type::Number, false)]);
+        self.block_body(depth);
+        self.scopes.pop();
+        self.line("}");
+    }
+
+    fn counter(&mut self) -> String {
+        self.counters += 1;
+        format!("i{}", self.counters)
+    }
+
+    /// A condition for an `if`, now and then one the optimizer can decide.
+    fn condition(&mut self) -> String {
+        match self.rng.below(6) {
+            0 => self.rng.choose(&["true", "false", "nil", "0", "\"\""]).to_string(),
+            _ => self.expr(Type::Boolean, 0),
+        }
+    }
+
+    /// The variables in scope, each under the innermost declaration of its name.
+    fn visible(&self) -> Vec<(String, Type, bool)> {
+        let mut visible: Vec<(String, Type, bool)> = Vec::new();
+        for scope in self.scopes.iter().rev() {
+            for variable in scope {
+                if !visible.iter().any(|seen| seen.0 == variable.0) {
+                    visible.push(variable.clone());
+                }
+            }
+        }
+        visible
+    }
+
+    fn variable(&mut self, ty: Type) -> Option<String> {
+        let names: Vec<String> = self.visible().into_iter().filter(|v| v.1 == ty).map(|v| v.0).collect();
+        if names.is_empty() {
+            None
+        } else {
+            Some(self.rng.choose(&names).clone())
+        }
+    }
+
+    fn any_expr(&mut self, depth: usize) -> String {
+        let ty = *self.rng.choose(TYPES);
+        self.expr(ty, depth)
+    }
+
+    fn expr(&mut self, ty: Type, depth: usize) -> String {
+        // Now and then, an expression that fails at runtime.
+        if self.rng.one_in(2500) {
+            return match self.rng.below(3) {
+                0 => format!("-{}", self.expr(Type::String, MAX_EXPR_DEPTH)),
+                1 => format!("({} - {})", self.expr(Type::String, depth + 1), self.expr(Type::Number, depth + 1)),
+                _ => "undefined".to_owned(),
+            };
+        }
+        if depth >= MAX_EXPR_DEPTH || self.rng.one_in(3) {
+            return self.leaf(ty);
+        }
+        let depth = depth + 1;
+        match ty {
+            Type::Number => match self.rng.below(6) {
+                0 => format!("-{}", self.expr(Type::Number, depth)),
+                1 => format!("({})", self.expr(Type::Number, depth)),
+                // Dividing by zero is an error, and loop counters start at
+                // zero, so mostly divide by a literal that isn't.
+                2 if self.rng.one_in(20) => {
+                    let operator = self.rng.choose(&["/", "~/", "%"]);
+                    format!("({} {} {})", self.expr(Type::Number, depth), operator, self.expr(Type::Number, depth))
+                }
+                2 => {
+                    let operator = self.rng.choose(&["/", "~/", "%"]);
+                    format!("({} {} {})", self.expr(Type::Number, depth), operator, self.rng.below(19) + 1)
+                }
+                _ => {
+                    let operator = self.rng.choose(&["+", "-", "*"]);
+                    format!("({} {} {})", self.expr(Type::Number, depth), operator, self.expr(Type::Number, depth))
+                }
+            },
+            Type::String => format!("({} + {})", self.expr(Type::String, depth), self.expr(Type::String, depth)),
+            Type::Boolean => match self.rng.below(6) {
+                0 => format!("!{}", self.any_expr(depth)),
+                1 => {
+                    let operator = self.rng.choose(&["<", "<=", ">", ">="]);
+                    format!("({} {} {})", self.expr(Type::Number, depth), operator, self.expr(Type::Number, depth))
+                }
+                2 | 3 => {
+                    let operator = self.rng.choose(&["==", "!="]);
+                    format!("({} {} {})", self.any_expr(depth), operator, self.any_expr(depth))
+                }
+                _ => {
+                    let operator = self.rng.choose(&["and", "or"]);
+                    format!("({} {} {})", self.expr(Type::Boolean, depth), operator, self.expr(Type::Boolean, depth))
+                }
+            },
+        }
+    }
+
+    fn leaf(&mut self, ty: Type) -> String {
+        if self.rng.one_in(2) {
+            if let Some(name) = self.variable(ty) {
+                return name;
+            }
+        }
+        match ty {
+            Type::Number => {
+                let number = self.rng.below(20);
+                if self.rng.one_in(3) {
+                    format!("{}.5", number)
+                } else {
+                    number.to_string()
+                }
+            }
+            Type::String => format!("\"{}\"", self.rng.choose(&["", "x", "yz", "lox"])),
+            Type::Boolean => self.rng.choose(&["true", "false"]).to_string(),
+        }
+    }
+
+    fn line(&mut self, line: &str) {
+        self.source.push_str(line);
+        self.source.push('\n');
+    }
+}
+
+/// What a program printed, and the error it failed with, if any.
+type Outcome = (String, Option<String>);
+
+/// Runs a program, or returns `None` if it doesn't parse or takes more than
+/// `max_steps` steps.
+fn run(source: &str, optimize: bool, max_steps: Option<u64>) -> Option<Outcome> {
+    let output = OutputBuffer::new();
+    let mut lox = Lox::new();
+    lox.set_output(output.clone());
+    lox.set_optimize(optimize);
+    // A string assigned to itself doubled in a loop soon gets out of hand, and
+    // the memory limit stops it the same way whether or not it's optimized.
+    // The loops are bounded, but a generator bug shouldn't hang the test.
+    lox.set_limits(Limits {
+        max_steps,
+        max_memory: Some(1 << 16),
+        max_wall_time: Some(Duration::from_secs(10)),
+        ..Limits::default()
+    });
+    let error = match lox.run(source) {
+        Ok(()) => None,
+        Err(LoxError::Syntax(_)) => return None,
+        Err(LoxError::Runtime(ref err)) if matches!(err.limit, Some(Limit::Steps(_))) => return None,
+        Err(err) => Some(err.to_string()),
+    };
+    Some((output.contents(), error))
+}
+
+/// Whether a program parses and behaves differently when optimized.
+fn diverges(source: &str) -> bool {
+    match (run(source, false, Some(SHRINK_MAX_STEPS)), run(source, true, Some(SHRINK_MAX_STEPS))) {
+        (Some(reference), Some(optimized)) => reference != optimized,
+        _ => false,
+    }
+}
+
+/// Cuts a program down to fewer lines for which `interesting` still holds, by
+/// removing runs of lines from every position, halving their length down to
+/// single lines, and starting over for as long as that removes anything.
+fn shrink<F: Fn(&str) -> bool>(source: &str, interesting: F) -> String {
+    let mut lines: Vec<&str> = source.lines().collect();
+    loop {
+        let before = lines.len();
+        let mut chunk = lines.len().div_ceil(2);
+        while chunk > 0 {
+            let mut start = 0;
+            while start < lines.len() {
+                let mut candidate = lines.clone();
+                candidate.drain(start..(start + chunk).min(lines.len()));
+                if interesting(&join(&candidate)) {
+                    lines = candidate;
+                } else {
+                    start += 1;
+                }
+            }
+            chunk /= 2;
+        }
+        if lines.len() == before {
+            return join(&lines);
+        }
+    }
+}
+
+fn join(lines: &[&str]) -> String {
+    lines.iter().map(|line| format!("{}\n", line)).collect()
+}
+
+#[test]
+fn test_optimized_programs_behave_the_same() {
+    let seeds: Vec<u64> = match env::var("LOX_DIFFERENTIAL_SEED") {
+        Ok(seed) => vec![seed.parse().expect("LOX_DIFFERENTIAL_SEED should be a number")],
+        Err(_) => (0..PROGRAMS).collect(),
+    };
+
+    let mut failed = 0;
+    for seed in seeds {
+        let source = Generator::new(seed).program();
+        let reference = run(&source, false, None);
+        let optimized = run(&source, true, None);
+        assert!(reference.is_some(), "generated a program that doesn't parse:\n{}", source);
+        if reference != optimized {
+            failed += 1;
+            // Shrinking takes a while, so only the first failure is shrunk.
+            if failed > 1 {
+                eprintln!("seed {} diverged too", seed);
+                continue;
+            }
+            let shrunk = shrink(&source, diverges);
+            eprintln!(
+                "seed {} diverged, shrunk to:\n{}\nas written: {:?}\noptimized:  {:?}\n",
+                seed,
+                shrunk,
+                run(&shrunk, false, Some(SHRINK_MAX_STEPS)),
+                run(&shrunk, true, Some(SHRINK_MAX_STEPS))
+            );
+        }
+    }
+    assert_eq!(failed, 0, "{} programs behaved differently when optimized", failed);
+}
+
+#[test]
+fn test_shrinks_to_the_lines_that_matter() {
+    let prints_x = |source: &str| match run(source, false, Some(SHRINK_MAX_STEPS)) {
+        Some((output, None)) => output.contains('x'),
+        _ => false,
+    };
+    // Without its increment the loop never ends, so that line has to go with
+    // the rest of the loop.
+    let source = "var a = 1;\n{\n  print a;\n}\nwhile (a < 3) {\n  a = a + 1;\n}\nprint \"x\";\nprint a;\n";
+    assert_eq!(shrink(source, prints_x), "print \"x\";\n");
+}
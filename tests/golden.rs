@@ -0,0 +1,122 @@
+//! Runs every script under `tests/lox` through the `rlox` binary and checks its
+//! output against the annotations in the script, in the style of the Crafting
+//! Interpreters test suite:
+//!
+//! * `// expect: <value>` - a line the script prints to stdout.
+//! * `// expect runtime error: <message>` - the script fails with exit code 70,
+//!   reporting the message on the annotated line.
+//! * `// Error<location>: <message>` or `// [line <n>] Error<location>: <message>` -
+//!   a compile error that makes the script fail with exit code 65.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const EXPECT: &str = "// expect: ";
+const EXPECT_RUNTIME_ERROR: &str = "// expect runtime error: ";
+
+struct Expectations {
+    output: Vec<String>,
+    errors: Vec<String>,
+    exit_code: i32,
+}
+
+impl Expectations {
+    fn parse(source: &str) -> Self {
+        let mut expectations = Expectations {
+            output: Vec::new(),
+            errors: Vec::new(),
+            exit_code: 0,
+        };
+
+        for (i, line) in source.lines().enumerate() {
+            let line_number = i + 1;
+            if let Some(index) = line.find(EXPECT) {
+                expectations.output.push(line[index + EXPECT.len()..].to_owned());
+            } else if let Some(index) = line.find(EXPECT_RUNTIME_ERROR) {
+                let message = &line[index + EXPECT_RUNTIME_ERROR.len()..];
+                expectations.errors.push(message.to_owned());
+                expectations.errors.push(format!("[line {}]", line_number));
+                expectations.exit_code = 70;
+            } else if let Some(index) = line.find("// [line ") {
+                expectations.errors.push(line[index + 3..].to_owned());
+                expectations.exit_code = 65;
+            } else if let Some(index) = line.find("// Error") {
+                expectations.errors.push(format!("[line {}] {}", line_number, &line[index + 3..]));
+                expectations.exit_code = 65;
+            }
+        }
+        expectations
+    }
+}
+
+fn collect_scripts(dir: &Path, scripts: &mut Vec<PathBuf>) {
+    for entry in fs::read_dir(dir).expect("test directory should be readable") {
+        let path = entry.expect("test directory entry should be readable").path();
+        if path.is_dir() {
+            collect_scripts(&path, scripts);
+        } else if path.extension().is_some_and(|ext| ext == "lox") {
+            scripts.push(path);
+        }
+    }
+}
+
+fn lines(bytes: &[u8]) -> Vec<String> {
+    String::from_utf8_lossy(bytes).lines().map(str::to_owned).collect()
+}
+
+fn check_script(path: &Path, args: &[&str]) -> Result<(), String> {
+    let source = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let expected = Expectations::parse(&source);
+
+    let result = Command::new(env!("CARGO_BIN_EXE_rlox"))
+        .args(args)
+        .arg(path)
+        .output()
+        .map_err(|e| e.to_string())?;
+
+    let mut failures = Vec::new();
+    let output = lines(&result.stdout);
+    if output != expected.output {
+        failures.push(format!("expected output {:?}\n    got {:?}", expected.output, output));
+    }
+    let errors = lines(&result.stderr);
+    if errors != expected.errors {
+        failures.push(format!("expected errors {:?}\n    got {:?}", expected.errors, errors));
+    }
+    let exit_code = result.status.code().unwrap_or(-1);
+    if exit_code != expected.exit_code {
+        failures.push(format!("expected exit code {}, got {}", expected.exit_code, exit_code));
+    }
+
+    if failures.is_empty() {
+        Ok(())
+    } else {
+        Err(failures.join("\n  "))
+    }
+}
+
+#[test]
+fn test_lox_scripts() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("lox");
+    let mut scripts = Vec::new();
+    collect_scripts(&root, &mut scripts);
+    scripts.sort();
+    assert!(!scripts.is_empty(), "no scripts found under {}", root.display());
+
+    // Every script should behave the same whether or not it's optimized.
+    let failures: Vec<String> = scripts
+        .iter()
+        .flat_map(|path| vec![(path, vec![]), (path, vec!["-O"])])
+        .filter_map(|(path, args)| {
+            check_script(path, &args).err().map(|failure| {
+                let name = path.strip_prefix(&root).unwrap_or(path).display();
+                format!("{} {}:\n  {}", name, args.join(" "), failure)
+            })
+        })
+        .collect();
+
+    if !failures.is_empty() {
+        panic!("{} of {} scripts failed:\n\n{}", failures.len(), scripts.len(), failures.join("\n\n"));
+    }
+}